@@ -0,0 +1,71 @@
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand, ValueEnum};
+use crate::models::{Format, DEFAULT_PROFILE};
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli{
+    #[command(subcommand)]
+    pub task: Option<Task>,
+}
+
+#[derive(Subcommand)]
+pub enum Task{
+    /// Watch Mastodon mentions and deliver them to the sinks (default)
+    Run,
+    /// Print the JSON Schema of the records published to Zinc
+    Schema,
+    /// Print feedback statistics from the records published to Zinc
+    Stats{
+        /// Number of days to look back
+        #[arg(short, long, default_value_t = 30)]
+        days: i64,
+    },
+    /// Export the processed feedback from the local database
+    Export{
+        #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        /// Only feedback in this category
+        #[arg(short, long)]
+        category: Option<String>,
+        /// Only feedback from this account, as user@instance
+        #[arg(short, long)]
+        account: Option<String>,
+        #[arg(short, long, value_enum)]
+        status: Option<Status>,
+        /// First day, as YYYY-MM-DD
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// Last day, as YYYY-MM-DD
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// File to write, standard output by default
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Authorize the watchdog in a service and store the token
+    Auth{
+        #[command(subcommand)]
+        service: Service,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum Service{
+    /// Register the watchdog in a Mastodon instance and authorize an account
+    Mastodon{
+        /// Base URI of the instance, MASTODON_BASE_URI by default
+        #[arg(short, long)]
+        base_uri: Option<String>,
+        /// Profile that will use the token
+        #[arg(short, long, default_value = DEFAULT_PROFILE)]
+        profile: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Status{
+    /// Answered questions and applied ideas
+    Answered,
+    Unanswered,
+}
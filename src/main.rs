@@ -1,285 +1,1205 @@
+mod cli;
 mod models;
 use html2md::parse_html;
 
+use chrono::{DateTime, Duration, Utc};
+use clap::Parser;
+use cli::{Cli, Service, Status, Task};
 use dotenv::dotenv;
-use std::{thread, time, env, str::FromStr};
+use futures::future::join_all;
+use std::{time, env, str::FromStr, sync::{Arc, Mutex}};
 use tracing_subscriber::{
     EnvFilter,
+    Layer,
     layer::SubscriberExt,
     util::SubscriberInitExt,
 };
+use models::oauth;
 use models::{
+    Command,
     Config,
+    Context,
+    Credential,
+    Credentials,
+    Delivery,
+    Digest,
+    Error,
+    Event,
     Feedback,
+    Filter,
+    FeedbackSink,
+    HttpSink,
+    Webhook,
+    get_fields,
+    read_webhooks,
     Zinc,
+    ZincBuffer,
+    ZincLayer,
     Mastodon,
     Matrix,
-    check_comment,
-    check_key,
+    Archive,
+    MediaArchive,
+    Mention,
+    Metadata,
+    Notified,
+    Acknowledge,
+    Action,
+    Profile,
+    Record,
+    Software,
+    Timeline,
+    Stats,
+    Store,
+    Templates,
+    Thread,
+    Threads,
+    export,
+    get_state,
+    compare_ids,
+    qualify,
+    read_profiles,
+    DEFAULT_PROFILE,
+    next_run,
+    parse_schedule,
+    MAX_TOOT_LENGTH,
+    ANSWERED,
+    APPLIED,
+    REJECTED,
+    RETRACTED,
 };
+use cron::Schedule;
 use serde_json::{Value, json};
-use tracing::{debug, error};
+use tracing::{debug, error, instrument, warn};
 
 const FILENAME: &str = "lastid.toml";
+const THREADS_FILENAME: &str = "threads.toml";
+const WEBHOOKS_FILENAME: &str = "webhooks.toml";
+const PROFILES_FILENAME: &str = "profiles.toml";
+const CREDENTIALS_FILENAME: &str = "credentials.toml";
+const NOTIFIED_FILENAME: &str = "notified.toml";
+const DATABASE_FILENAME: &str = "watchdog.db";
+const ZINC_SPOOL: &str = "zinc.spool";
+const ZINC_LOGS_SPOOL: &str = "zinc-logs.spool";
+
+// Mastodon account watched by the watchdog
+struct Account{
+    profile: Profile,
+    mastodon: Mastodon,
+    // Id of the watched account, whose own statuses are not feedback
+    id: Option<String>,
+}
+
+struct Watchdog{
+    feedback: FeedbackSink,
+    // 1 is the original payload, 2 adds the status metadata
+    feedback_format: u32,
+    webhooks: Vec<Webhook>,
+    webhook_sink: HttpSink,
+    // The first account also posts the digests
+    accounts: Vec<Account>,
+    matrix: Matrix,
+    room_id: String,
+    moderators: Vec<String>,
+    zinc: Arc<ZincBuffer>,
+    store: Store,
+    threads: Mutex<Threads>,
+    digest_schedule: Option<Schedule>,
+    digest_mastodon: bool,
+    digest_visibility: String,
+    // Messages for the authors of applied feedback, when enabled
+    templates: Option<Templates>,
+    // Statuses processed in the last days are checked for edits and
+    // deletions, every interval. Zero days disables it.
+    recheck_days: i64,
+    recheck_interval: time::Duration,
+    recheck_batch: usize,
+    // Copies the attachments of the mentions, when enabled
+    media: Option<MediaArchive>,
+}
 
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    let cli = Cli::parse();
+    match cli.task.unwrap_or(Task::Run){
+        Task::Run => run().await,
+        Task::Schema => println!("{}", Event::json_schema()),
+        Task::Stats{days} => {
+            let zinc = Zinc::new(
+                &env::var("ZINC_BASE_URL").expect("Not found zinc base url"),
+                &env::var("ZINC_INDICE").expect("Not found zinc indice"),
+                &env::var("ZINC_TOKEN").expect("Not found token"));
+            let to = Utc::now();
+            let from = to - chrono::Duration::days(days);
+            match Stats::query(&zinc, from, to).await{
+                Ok(stats) => print!("{}", stats.to_text()),
+                Err(e) => eprintln!("Can not query Zinc: {}", e),
+            }
+        },
+        Task::Auth{service: Service::Mastodon{base_uri, profile}} => {
+            let base_uri = base_uri.or(env::var("MASTODON_BASE_URI").ok())
+                .expect("Not found Mastodon Base Uri");
+            let filename = env::var("CREDENTIALS_FILE").unwrap_or(CREDENTIALS_FILENAME.to_string());
+            match auth_mastodon(&base_uri, &profile, &filename).await{
+                Ok(account) => println!("Authorized {} for profile {}", account, profile),
+                Err(e) => eprintln!("Can not authorize: {}", e),
+            }
+        },
+        Task::Export{format, category, account, status, from, to, output} => {
+            let store = Store::open(&env::var("DATABASE").unwrap_or(DATABASE_FILENAME.to_string()))
+                .expect("Can not open database");
+            let filter = Filter{
+                category,
+                account,
+                answered: status.map(|status| status == Status::Answered),
+                from: from.map(|from| from.and_hms_opt(0, 0, 0).unwrap().and_utc()),
+                to: to.map(|to| (to + chrono::Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc()),
+            };
+            let exported = store.records(&filter)
+                .and_then(|records| export(&records, format, from, to));
+            match (exported, output){
+                (Ok(exported), Some(output)) => std::fs::write(&output, exported)
+                    .unwrap_or_else(|e| eprintln!("Can not write {}: {}", output, e)),
+                (Ok(exported), None) => print!("{}", exported),
+                (Err(e), _) => eprintln!("Can not export: {}", e),
+            }
+        },
+    }
+}
+
+// Registers the watchdog in the instance, asks the user to authorize it and
+// stores the token for the profile
+async fn auth_mastodon(base_uri: &str, profile: &str, filename: &str) -> Result<String, Error>{
+    let base_uri = base_uri.trim_end_matches('/');
+    let app = oauth::register_app(base_uri).await?;
+    println!("Open this url, authorize the watchdog and paste the code:");
+    println!("{}", oauth::get_authorize_url(base_uri, &app)?);
+    let mut code = String::new();
+    std::io::stdin().read_line(&mut code)?;
+    let access_token = oauth::get_token(base_uri, &app, code.trim()).await?;
+    let response = Mastodon::new(base_uri, &access_token).verify_credentials().await?;
+    let account = serde_json::from_str::<Value>(&response)?
+        .get("acct")
+        .and_then(|acct| acct.as_str())
+        .map(|acct| acct.to_string())
+        .ok_or(format!("Unexpected account {}", response))?;
+    let mut credentials = Credentials::read(filename)?;
+    credentials.insert(profile, Credential{
+        base_uri: base_uri.to_string(),
+        client_id: app.client_id,
+        client_secret: app.client_secret,
+        access_token,
+        account: account.to_string(),
+    });
+    credentials.save(filename)?;
+    Ok(account)
+}
+
+async fn run() {
+    let zinc_base_url = env::var("ZINC_BASE_URL").expect("Not found zinc base url");
+    let zinc_indice = env::var("ZINC_INDICE").expect("Not found zinc indice");
+    let zinc_token = env::var("ZINC_TOKEN").expect("Not found token");
+    let zinc_batch_size = env::var("ZINC_BATCH_SIZE")
+        .unwrap_or("50".to_string())
+        .parse::<usize>()
+        .expect("ZINC_BATCH_SIZE must be a number");
+    let zinc_flush_interval = env::var("ZINC_FLUSH_INTERVAL")
+        .unwrap_or("30".to_string())
+        .parse::<u64>()
+        .expect("ZINC_FLUSH_INTERVAL must be a number");
+    let zinc_retries = env::var("ZINC_RETRIES")
+        .unwrap_or("3".to_string())
+        .parse::<u32>()
+        .expect("ZINC_RETRIES must be a number");
+    let zinc_spool = env::var("ZINC_SPOOL").unwrap_or(ZINC_SPOOL.to_string());
+    let zinc_spool_max = env::var("ZINC_SPOOL_MAX")
+        .unwrap_or("10000".to_string())
+        .parse::<usize>()
+        .expect("ZINC_SPOOL_MAX must be a number");
+    let zinc = Arc::new(ZincBuffer::new(
+        Zinc::new(&zinc_base_url, &zinc_indice, &zinc_token),
+        zinc_batch_size,
+        zinc_retries,
+        &zinc_spool,
+        zinc_spool_max));
+    zinc.spawn(time::Duration::from_secs(zinc_flush_interval));
+    // Logs are not events, so they are only shipped to an index of their own
+    let zinc_logs = env::var("ZINC_LOG_INDICE").ok().map(|zinc_log_indice| {
+        if zinc_log_indice == zinc_indice{
+            panic!("ZINC_LOG_INDICE must not be the index of the events");
+        }
+        let zinc_logs = Arc::new(ZincBuffer::new(
+            Zinc::new(&zinc_base_url, &zinc_log_indice, &zinc_token),
+            zinc_batch_size,
+            zinc_retries,
+            ZINC_LOGS_SPOOL,
+            zinc_spool_max));
+        zinc_logs.spawn(time::Duration::from_secs(zinc_flush_interval));
+        zinc_logs
+    });
+
     let log_level = env::var("LOG_LEVEL").unwrap_or("DEBUG".to_string());
+    let zinc_log_level = env::var("ZINC_LOG_LEVEL").unwrap_or("INFO".to_string());
     tracing_subscriber::registry()
-        .with(EnvFilter::from_str(&log_level).unwrap())
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer()
+            .with_filter(EnvFilter::from_str(&log_level).unwrap()))
+        .with(zinc_logs.clone().map(|zinc_logs| ZincLayer::new(zinc_logs)
+            .with_filter(EnvFilter::from_str(&zinc_log_level).unwrap())))
         .init();
 
     let mut config = Config::read("lastid.toml").expect("Can not read last id");
-    let mut last_id = config.get_last_id().to_string();
     let url = env::var("URL")
         .expect("Not found URL");
     let token = env::var("TOKEN")
         .expect("Not found TOKEN");
+    let feedback_retries = env::var("FEEDBACK_RETRIES")
+        .unwrap_or("3".to_string())
+        .parse::<u32>()
+        .expect("FEEDBACK_RETRIES must be a number");
+    let feedback_connect_timeout = env::var("FEEDBACK_CONNECT_TIMEOUT")
+        .unwrap_or("10".to_string())
+        .parse::<u64>()
+        .expect("FEEDBACK_CONNECT_TIMEOUT must be a number");
+    let feedback_timeout = env::var("FEEDBACK_TIMEOUT")
+        .unwrap_or("30".to_string())
+        .parse::<u64>()
+        .expect("FEEDBACK_TIMEOUT must be a number");
     let sleep_time_in_seconds = env::var("SLEEP_TIME")
         .expect("Not found SLEEP_TIME")
         .parse::<u64>()
         .unwrap();
     let sleep_time = time::Duration::from_secs(sleep_time_in_seconds);
-    let mastodon_base_uri = env::var("MASTODON_BASE_URI").expect("Not found Mastodon Base Uri");
-    let mastodon_token = env::var("MASTODON_ACCESS_TOKEN").expect("Not found Mastodon token");
-    let mastodon = Mastodon::new(&mastodon_base_uri, &mastodon_token);
+    let mut profiles = read_profiles(&env::var("PROFILES_FILE").unwrap_or(PROFILES_FILENAME.to_string()))
+        .expect("Can not read profiles");
+    let credentials = Credentials::read(&env::var("CREDENTIALS_FILE").unwrap_or(CREDENTIALS_FILENAME.to_string()))
+        .expect("Can not read credentials");
+    if profiles.is_empty(){
+        let credential = credentials.get(DEFAULT_PROFILE);
+        let mastodon_base_uri = env::var("MASTODON_BASE_URI").ok()
+            .or(credential.map(|credential| credential.base_uri.to_string()))
+            .expect("Not found Mastodon Base Uri");
+        let mastodon_token = env::var("MASTODON_ACCESS_TOKEN").ok()
+            .or(credential.map(|credential| credential.access_token.to_string()))
+            .expect("Not found Mastodon token");
+        let mut profile = Profile::new(DEFAULT_PROFILE, &mastodon_base_uri, &mastodon_token);
+        profile.searches = env::var("MASTODON_SEARCH").unwrap_or_default()
+            .split(',')
+            .map(|query| query.trim().to_string())
+            .filter(|query| !query.is_empty())
+            .collect();
+        // Like idea=favourite,pregunta=reply
+        profile.actions.extend(env::var("MASTODON_ACTIONS").unwrap_or_default()
+            .split(',')
+            .filter_map(|item| item.split_once('='))
+            .map(|(category, action)| (category.trim().to_string(), action.trim().parse::<Action>()
+                .expect("MASTODON_ACTIONS must be reply, favourite, bookmark, boost or none"))));
+        profile.software = env::var("MASTODON_SOFTWARE").ok()
+            .map(|software| software.parse::<Software>()
+                .expect("MASTODON_SOFTWARE must be mastodon, pleroma, akkoma, gotosocial or misskey"));
+        profile.acknowledge = env::var("MASTODON_ACKNOWLEDGE")
+            .unwrap_or("none".to_string())
+            .parse::<Acknowledge>()
+            .expect("MASTODON_ACKNOWLEDGE must be none, dismiss or marker");
+        profiles.push(profile);
+    }
+    for profile in profiles.iter_mut().filter(|profile| profile.token.is_empty()){
+        profile.token = credentials.get(&profile.name)
+            .map(|credential| credential.access_token.to_string())
+            .unwrap_or_else(|| panic!("Not found token for profile {}, run `auth mastodon`", profile.name));
+    }
+    let mut accounts = Vec::new();
+    for profile in profiles{
+        let mastodon = Mastodon::new(&profile.base_uri, &profile.token);
+        let software = match profile.software{
+            Some(software) => software,
+            None => detect_software(&mastodon).await,
+        };
+        debug!("Profile {} on {:?}", profile.name, software);
+        let id = get_account_id(&mastodon).await;
+        accounts.push(Account{
+            mastodon: mastodon.with_software(software),
+            profile,
+            id,
+        });
+    }
     let matrix_base_url = env::var("MATRIX_BASE_URL").expect("Not found Matrix base url");
     let matrix_token = env::var("MATRIX_TOKEN").expect("Not found Matrix token");
     let matrix_room_id = env::var("MATRIX_ROOM_ID").expect("Not found Matrix room_id");
     let matrix = Matrix::new(matrix_base_url, matrix_token);
+    let matrix_moderators: Vec<String> = env::var("MATRIX_MODERATORS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|moderator| moderator.trim().to_string())
+        .filter(|moderator| !moderator.is_empty())
+        .collect();
+    if matrix_moderators.is_empty(){
+        warn!("MATRIX_MODERATORS is not set, commands from Matrix are ignored");
+    }
 
-    let zinc_base_url = env::var("ZINC_BASE_URL").expect("Not found zinc base url");
-    let zinc_indice = env::var("ZINC_INDICE").expect("Not found zinc indice");
-    let zinc_token = env::var("ZINC_TOKEN").expect("Not found token");
-    let zinc = Zinc::new(&zinc_base_url, &zinc_indice, &zinc_token);
+    let watchdog = Watchdog{
+        feedback: FeedbackSink::new(&url, &token, feedback_retries,
+            time::Duration::from_secs(feedback_connect_timeout),
+            time::Duration::from_secs(feedback_timeout))
+            .with_secret(env::var("FEEDBACK_SECRET").ok()),
+        feedback_format: env::var("FEEDBACK_FORMAT")
+            .unwrap_or("1".to_string())
+            .parse::<u32>()
+            .expect("FEEDBACK_FORMAT must be a number"),
+        webhooks: read_webhooks(&env::var("WEBHOOKS_FILE").unwrap_or(WEBHOOKS_FILENAME.to_string()))
+            .expect("Can not read webhooks"),
+        webhook_sink: HttpSink::new(feedback_retries,
+            time::Duration::from_secs(feedback_connect_timeout),
+            time::Duration::from_secs(feedback_timeout)),
+        accounts,
+        matrix,
+        room_id: matrix_room_id,
+        moderators: matrix_moderators,
+        zinc,
+        store: Store::open(&env::var("DATABASE").unwrap_or(DATABASE_FILENAME.to_string()))
+            .expect("Can not open database"),
+        threads: Mutex::new(Threads::read(THREADS_FILENAME).expect("Can not read threads")),
+        digest_schedule: env::var("DIGEST_SCHEDULE").ok()
+            .map(|schedule| parse_schedule(&schedule).expect("DIGEST_SCHEDULE must be a cron expression")),
+        digest_mastodon: env::var("DIGEST_MASTODON")
+            .map(|value| value == "true")
+            .unwrap_or(false),
+        digest_visibility: env::var("DIGEST_VISIBILITY").unwrap_or("unlisted".to_string()),
+        templates: env::var("FEEDBACK_NOTIFY")
+            .is_ok_and(|value| value == "true")
+            .then(|| Templates{
+                applied: env::var("APPLIED_TEMPLATE")
+                    .unwrap_or("¡Gracias! Tu idea se ha aplicado[ en el episodio {{episode}}]".to_string()),
+                answered: env::var("ANSWERED_TEMPLATE")
+                    .unwrap_or("¡Gracias! Tu pregunta se ha respondido[ en el episodio {{episode}}]".to_string()),
+            }),
+        recheck_days: env::var("RECHECK_DAYS")
+            .unwrap_or("2".to_string())
+            .parse::<i64>()
+            .expect("RECHECK_DAYS must be a number"),
+        recheck_interval: time::Duration::from_secs(env::var("RECHECK_INTERVAL")
+            .unwrap_or("3600".to_string())
+            .parse::<u64>()
+            .expect("RECHECK_INTERVAL must be a number")),
+        recheck_batch: env::var("RECHECK_BATCH")
+            .unwrap_or("40".to_string())
+            .parse::<usize>()
+            .expect("RECHECK_BATCH must be a number"),
+        media: env::var("MEDIA_ARCHIVE").ok()
+            .map(|archive| MediaArchive::new(
+                archive.parse::<Archive>().expect("MEDIA_ARCHIVE must be matrix or directory:<path>"),
+                env::var("MEDIA_MAX_SIZE")
+                    .unwrap_or("10485760".to_string())
+                    .parse::<u64>()
+                    .expect("MEDIA_MAX_SIZE must be a number"),
+                env::var("MEDIA_TYPES")
+                    .unwrap_or("image/,audio/,video/".to_string())
+                    .split(',')
+                    .map(|content_type| content_type.trim().to_lowercase())
+                    .filter(|content_type| !content_type.is_empty())
+                    .collect())),
+    };
+    let mut notified = Notified::read(NOTIFIED_FILENAME).expect("Can not read notified");
+    let mut last_recheck: Option<time::Instant> = None;
+    let mut recheck_offset = 0;
     loop {
-        if let Some(new_last_id) = search(&url, &token, &mastodon, &matrix,
-                &matrix_room_id, &last_id, &zinc).await{
-            config.last_id = new_last_id.to_string();
+        let cursors = join_all(watchdog.accounts.iter()
+            .map(|account| poll(&watchdog, account, &config)))
+            .await;
+        for (key, cursor) in cursors.into_iter().flatten(){
+            config.set_cursor(&key, &cursor);
+            debug!("Save: {:?}", config.save(FILENAME));
+        }
+        if let Some(next_batch) = moderate(&watchdog, config.matrix_since.as_deref()).await{
+            config.matrix_since = Some(next_batch);
+            debug!("Save: {:?}", config.save(FILENAME));
+        }
+        if let Some(last_digest) = digest(&watchdog, config.last_digest).await{
+            config.last_digest = Some(last_digest);
             debug!("Save: {:?}", config.save(FILENAME));
-            last_id = new_last_id.to_string();
         }
-        thread::sleep(sleep_time);
+        if let Some(feedback_since) = notify(&watchdog, config.feedback_since.as_deref(), &mut notified).await{
+            config.feedback_since = Some(feedback_since);
+            debug!("Save: {:?}", config.save(FILENAME));
+        }
+        if watchdog.recheck_days > 0 && last_recheck.is_none_or(|last| last.elapsed() >= watchdog.recheck_interval){
+            recheck_offset = recheck(&watchdog, recheck_offset).await;
+            last_recheck = Some(time::Instant::now());
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(sleep_time) => {},
+            _ = tokio::signal::ctrl_c() => {
+                debug!("Zinc: flushing {} records before exit", watchdog.zinc.pending());
+                watchdog.zinc.flush().await;
+                if let Some(zinc_logs) = &zinc_logs{
+                    zinc_logs.flush().await;
+                }
+                break;
+            },
+        }
     }
 }
-async fn search(url: &str, token: &str, mastodon: &Mastodon, matrix: &Matrix, 
-        room_id: &str, last_id: &str, zinc: &Zinc) -> Option<String>{
+// Posts a digest of the feedback received since the last one to Matrix, and
+// optionally to Mastodon, when the schedule says it is due
+async fn digest(watchdog: &Watchdog, last_digest: Option<DateTime<Utc>>) -> Option<DateTime<Utc>>{
+    let schedule = watchdog.digest_schedule.as_ref()?;
+    let now = Utc::now();
+    let last_digest = match last_digest{
+        Some(last_digest) => last_digest,
+        None => return Some(now),
+    };
+    if next_run(schedule, last_digest)? > now{
+        return None;
+    }
+    let stats = match watchdog.store.stats(last_digest, now){
+        Ok(stats) => stats,
+        Err(error) => {
+            log_error("Store stats", &error);
+            return None;
+        },
+    };
+    let digest = Digest::new(stats);
+    if let Err(error) = watchdog.matrix.post_message(&watchdog.room_id, &digest.to_text(), &digest.to_html()).await{
+        log_error("Matrix digest", &error);
+        return None;
+    }
+    if watchdog.digest_mastodon{
+        let mut in_reply_to_id = None;
+        for toot in digest.to_toots(MAX_TOOT_LENGTH){
+            let mastodon = &watchdog.accounts[0].mastodon;
+            match mastodon.post_with_visibility(&toot, in_reply_to_id.take(), Some(&watchdog.digest_visibility)).await{
+                Ok(response) => {
+                    debug!("Mastodon post: {response}");
+                    in_reply_to_id = serde_json::from_str::<Value>(&response).ok()
+                        .and_then(|value| value.get("id")?.as_str().map(|id| id.to_string()));
+                },
+                Err(error) => {
+                    log_error("Mastodon digest", &error);
+                    break;
+                },
+            }
+        }
+    }
+    Some(now)
+}
+
+// Replies to the authors of the feedback applied or answered since the last
+// poll, and returns the new cursor when every change was handled
+async fn notify(watchdog: &Watchdog, since: Option<&str>, notified: &mut Notified) -> Option<String>{
+    let templates = watchdog.templates.as_ref()?;
+    let changes = match watchdog.feedback.changes(since).await{
+        Ok(changes) => changes,
+        Err(error) => {
+            log_error("Feedback changes", &error);
+            return None;
+        },
+    };
+    let mut complete = true;
+    for change in changes.iter().filter(|change| change.applied == 1){
+        let (state, message) = match (get_state(change), templates.render(change)){
+            (Some(state), Some(message)) => (state, message),
+            _ => continue,
+        };
+        let record = resolve(watchdog, &change.reference);
+        // Feedback sent before references had a profile is known by its id
+        let reference = match &record{
+            Ok(Some(record)) => qualify(&record.profile, &record.notification_id),
+            _ => change.reference.to_string(),
+        };
+        if notified.contains(&reference, state){
+            continue;
+        }
+        let result = match &record{
+            Ok(record) => reply(watchdog, record.as_ref(), &change.reference, &message).await,
+            Err(error) => Err(error.to_string().into()),
+        }.map_err(|e| e.to_string());
+        match &result{
+            Ok(response) => {
+                debug!("Notified {} {}: {}", state, change.reference, response);
+                notified.insert(&reference, state);
+                debug!("Save: {:?}", notified.save(NOTIFIED_FILENAME));
+                if let Ok(Some(record)) = &record{
+                    if let Err(error) = watchdog.store.set_state(&record.profile, &record.notification_id, state){
+                        log_error("Store", &error);
+                    }
+                }
+            },
+            Err(e) => {
+                error!("Notify {} {}: {}", state, change.reference, e);
+                complete = false;
+            },
+        }
+        watchdog.zinc.push(&Event::command(&format!("notify_{}", state), &change.reference, "mastodon", &result).to_value());
+    }
+    if !complete{
+        return None;
+    }
+    changes.iter()
+        .filter_map(|change| change.updated_at.clone())
+        .max()
+}
+
+async fn moderate(watchdog: &Watchdog, since: Option<&str>) -> Option<String>{
+    let matrix = &watchdog.matrix;
+    let room_id = watchdog.room_id.as_str();
+    let (next_batch, messages) = match matrix.get_messages(room_id, since).await{
+        Ok(value) => value,
+        Err(e) => {
+            error!("Matrix sync: {:?}", e);
+            return None;
+        },
+    };
+    // Without a previous sync token the timeline is history, not new commands
+    if since.is_none(){
+        return Some(next_batch);
+    }
+    for message in messages{
+        let command = match Command::parse(&message.body){
+            Some(command) => command,
+            None => continue,
+        };
+        // Without moderators nobody can run commands
+        if !watchdog.moderators.contains(&message.sender){
+            debug!("Ignored command from {}: {}", message.sender, message.body);
+            continue;
+        }
+        debug!("Command from {}: {:?}", message.sender, command);
+        let result = execute(watchdog, &command).await.map_err(|e| e.to_string());
+        let reaction = match &result{
+            Ok(response) => {
+                debug!("Command response: {response}");
+                "✅"
+            },
+            Err(e) => {
+                error!("Command {:?}: {}", command, e);
+                "❌"
+            },
+        };
+        let sink = match command{
+            Command::Reply{..} => "mastodon",
+            _ => "feedback",
+        };
+        watchdog.zinc.push(&Event::command(command.get_name(), command.get_id(), sink, &result).to_value());
+        debug!("Response: {:?}", matrix.send_reaction(room_id, &message.event_id, reaction).await);
+    }
+    Some(next_batch)
+}
+
+#[instrument(skip(watchdog))]
+async fn execute(watchdog: &Watchdog, command: &Command) -> Result<String, Error>{
+    let feedback = &watchdog.feedback;
+    let store = &watchdog.store;
+    let id = command.get_id();
+    let record = resolve(watchdog, id)?;
+    // Mentions processed before the store existed are known to the feedback
+    // service by their notification id
+    let feedback_id = record.as_ref()
+        .and_then(|record| record.feedback_id.clone())
+        .unwrap_or(id.to_string());
+    // Each profile has the categories of its rules
+    if let Command::Recategorize{category, ..} = command{
+        let profile = &get_account(watchdog, record.as_ref().map(|record| record.profile.as_str())).profile;
+        if !profile.has_category(category){
+            return Err(format!("Unknown category {} in profile {}", category, profile.name).into());
+        }
+    }
+    let response = match command{
+        Command::Reply{text, ..} => reply(watchdog, record.as_ref(), id, text).await?,
+        Command::Approve{..} => feedback.update(&feedback_id, &json!({"applied": 1})).await?,
+        Command::Reject{..} => feedback.update(&feedback_id, &json!({"applied": -1})).await?,
+        Command::Recategorize{category, ..} => {
+            feedback.update(&feedback_id, &json!({"category": category})).await?
+        },
+    };
+    let record = match record{
+        Some(record) => record,
+        None => {
+            error!("Store: Unknown notification {}", id);
+            return Ok(response);
+        },
+    };
+    let (profile, notification_id) = (&record.profile, &record.notification_id);
+    let stored = match command{
+        Command::Reply{..} => store.set_state(profile, notification_id, ANSWERED),
+        Command::Approve{..} => store.set_state(profile, notification_id, APPLIED),
+        Command::Reject{..} => store.set_state(profile, notification_id, REJECTED),
+        Command::Recategorize{category, ..} => store.set_category(profile, notification_id, category),
+    };
+    if let Err(error) = stored{
+        log_error("Store", &error);
+    }
+    Ok(response)
+}
+
+// Mention of a notification id, written as `<profile>/<id>` when several
+// profiles got the same id
+fn resolve(watchdog: &Watchdog, id: &str) -> Result<Option<Record>, Error>{
+    if let Some((profile, notification_id)) = id.split_once('/'){
+        return watchdog.store.get(profile, notification_id);
+    }
+    let mut records = watchdog.store.find(id)?;
+    if records.len() > 1{
+        return Err(format!("Notification {} of several profiles, use <profile>/{}", id, id).into());
+    }
+    Ok(records.pop())
+}
+
+// Account of a profile, the first one when it is unknown
+fn get_account<'a>(watchdog: &'a Watchdog, profile: Option<&str>) -> &'a Account{
+    watchdog.accounts.iter()
+        .find(|account| Some(account.profile.name.as_str()) == profile)
+        .unwrap_or(&watchdog.accounts[0])
+}
+
+// Replies to the status of a mention, mentioning its author. Mentions found
+// by a search are only known by the store.
+async fn reply(watchdog: &Watchdog, record: Option<&Record>, id: &str, text: &str) -> Result<String, Error>{
+    let mastodon = &get_account(watchdog, record.map(|record| record.profile.as_str())).mastodon;
+    if let Some(record) = record{
+        let message = format!("@{} {}", record.account.trim_start_matches('@'), text);
+        return mastodon.post(&message, Some(record.status_id.to_string())).await;
+    }
+    let notification: Value = serde_json::from_str(&mastodon.notification(id).await?)?;
+    let status_id = notification.pointer("/status/id")
+        .and_then(|v| v.as_str())
+        .ok_or(format!("Notification {} without status", id))?;
+    let nickname = notification.pointer("/account/acct")
+        .and_then(|v| v.as_str())
+        .ok_or(format!("Notification {} without account", id))?;
+    let message = format!("@{} {}", nickname, text);
+    mastodon.post(&message, Some(status_id.to_string())).await
+}
+
+fn log_error(context: &str, error: &Error){
+    error!("{context}: {error}");
+    let mut next_err = error.source();
+    while next_err.is_some(){
+        error!("caused by: {:#}", next_err.unwrap());
+        next_err = next_err.unwrap().source();
+    }
+}
+
+// Polls the notifications and then the searches and timelines of the
+// account, so that statuses found twice are recorded with their notification.
+// Returns the cursors that changed.
+async fn poll(watchdog: &Watchdog, account: &Account, config: &Config) -> Vec<(String, String)>{
+    let mut cursors = Vec::new();
+    let name = &account.profile.name;
+    if let Some(cursor) = search(watchdog, account, config.get_cursor(name)).await{
+        cursors.push((name.to_string(), cursor));
+    }
+    for query in account.profile.searches.iter(){
+        let key = format!("{}:search:{}", name, query);
+        if let Some(cursor) = search_statuses(watchdog, account, query, config.get_cursor(&key)).await{
+            cursors.push((key, cursor));
+        }
+    }
+    for timeline in account.profile.timelines.iter(){
+        let key = format!("{}:timeline:{}", name, timeline.get_path());
+        if let Some(cursor) = timeline_statuses(watchdog, account, timeline, config.get_cursor(&key)).await{
+            cursors.push((key, cursor));
+        }
+    }
+    cursors
+}
+
+#[instrument(skip_all, fields(profile = %account.profile.name, query = %query))]
+async fn search_statuses(watchdog: &Watchdog, account: &Account, query: &str, min_id: &str) -> Option<String>{
+    let statuses = account.mastodon.search(query, min_id).await
+        .and_then(|response| Ok(serde_json::from_str::<Value>(&response)?))
+        .map(|data| data.get("statuses")
+            .and_then(|statuses| statuses.as_array())
+            .cloned()
+            .unwrap_or_default());
+    handle_statuses(watchdog, account, statuses, min_id, false).await
+}
+
+#[instrument(skip_all, fields(profile = %account.profile.name, timeline = %timeline.get_path()))]
+async fn timeline_statuses(watchdog: &Watchdog, account: &Account, timeline: &Timeline, min_id: &str) -> Option<String>{
+    let statuses = account.mastodon.timeline(&timeline.get_path(), min_id).await
+        .and_then(|response| Ok(serde_json::from_str::<Vec<Value>>(&response)?));
+    handle_statuses(watchdog, account, statuses, min_id, timeline.categorized).await
+}
+
+// Handles the statuses of a search or a timeline, newest first, and returns
+// the new cursor. With `categorized` only the statuses with the hashtag of a
+// rule are handled.
+async fn handle_statuses(watchdog: &Watchdog, account: &Account, statuses: Result<Vec<Value>, Error>,
+        min_id: &str, categorized: bool) -> Option<String>{
+    let statuses = match statuses{
+        Ok(statuses) => statuses,
+        Err(error) => {
+            log_error("Mastodon statuses", &error);
+            watchdog.zinc.push(&Event::poll_error(&error.to_string()).to_value());
+            return None;
+        },
+    };
+    watchdog.zinc.push(&Event::poll(statuses.len()).to_value());
+    // Without a cursor the results are history, only start from the newest
+    if min_id == "0"{
+        return statuses.iter()
+            .filter_map(|status| status.get("id")?.as_str())
+            .max_by(|a, b| compare_ids(a, b))
+            .map(|id| id.to_string());
+    }
+    let mut new_min_id = None;
+    for status in statuses.iter().rev(){
+        // Boosts in lists are not feedback from their author
+        let mention = Some(status)
+            .filter(|status| status.get("reblog").is_none_or(|reblog| reblog.is_null()))
+            .and_then(|status| Mention::from_status(status)
+                .or_else(|| {
+                    error!("Unexpected status: {}", status);
+                    None
+                }))
+            // Nor the own statuses, like the digests with the hashtags of the rules
+            .filter(|mention| account.id.as_ref() != Some(&mention.account_id))
+            .filter(|mention| !categorized || account.profile.is_categorized(&mention.content));
+        if let Some(mention) = mention{
+            // The cursor stops before a pending status, so it is retried
+            if !handle(watchdog, account, &mention).await{
+                break;
+            }
+        }
+        let id = status.get("id").and_then(|id| id.as_str());
+        if id.is_some_and(|id| new_min_id.as_deref().is_none_or(|new_min_id| compare_ids(id, new_min_id).is_gt())){
+            new_min_id = id.map(|id| id.to_string());
+        }
+    }
+    new_min_id.filter(|new_min_id| compare_ids(new_min_id, min_id).is_gt())
+}
+
+#[instrument(skip_all, fields(profile = %account.profile.name))]
+async fn search(watchdog: &Watchdog, account: &Account, last_id: &str) -> Option<String>{
     let mut new_last_id: String = "".to_string();
-    let res = mastodon.notifications(last_id).await;
+    let acknowledge = account.profile.acknowledge;
+    let marker = match acknowledge {
+        Acknowledge::Marker => get_marker(account).await,
+        _ => None,
+    };
+    // Notifications up to this one have been handled
+    let mut read_id = marker.clone();
+    let mut pending = false;
+    let since_id = marker.as_deref().unwrap_or(last_id);
+    let res = account.mastodon.notifications(since_id).await;
     //let res = mastodon.search(last_id).await;
-    if res.is_ok(){
-        let message = match res {
-            Ok(value) => value,
-            Err(e) => format!("Error: {}", e.to_string()),
-        };
-        match zinc.publish(&json!([{
-            "src": "Mastodon",
-            "type": "search",
-            "message": &message,
-        }])).await{
-                Ok(response) => debug!("Response: {:?}", response),
-                Err(e) => {
-                    error!("Message: {}", message);
-                    error!("Error: {:?}", e)
-                },
-            };
+    if let Ok((max_id, message)) = res{
         let data: Value =  match serde_json::from_str(&message){
             Ok(value) => value,
             Err(_) => json!([]),
         };
         //let statuses: Vec<Value> = data.get("statuses").unwrap().as_array().unwrap().to_vec();
-        let mentions: Vec<Value> = data.as_array().unwrap().to_vec();
+        let mentions: Vec<Value> = data.as_array().cloned().unwrap_or_default();
+        watchdog.zinc.push(&Event::poll(mentions.len()).to_value());
         let mentions_reversed: Vec<Value> = mentions.into_iter().rev().collect();
         //mentions.sort_by(|m1, m2| m1.get("id").unwrap().as_str().unwrap().cmp(m2.get("id").unwrap().as_str().unwrap()));
         //for status in statuses {
-        for mention in mentions_reversed {
-            new_last_id = mention.get("id").unwrap().as_str().unwrap().to_string();
-            let status = mention.get("status").unwrap();
-            let content = status.get("content").unwrap().as_str().unwrap();
-            let created_at = status.get("created_at").unwrap().as_str().unwrap();
-            let account = mention.get("account").unwrap();
-            let name = account.get("username").unwrap().as_str().unwrap();
-            let nickname = account.get("acct").unwrap().as_str().unwrap();
-            debug!("==========");
-            debug!("Text: {}", parse_html(content));
-            debug!("Id: {}", &new_last_id);
-            debug!("created_at: {}", created_at);
-            debug!("Name: {}", name);
-            debug!("Screen Name: {}", nickname);
-            if let Some(message) = check_key("idea", content){
-                let feedback = Feedback::new("idea", &new_last_id, &message, name, nickname, 0, "Mastodon");
-                match feedback.post(url, token).await{
-                    Ok(response) => debug!("Feedback response: {response}"),
-                    Err(error) => {
-                        error!("Feedback response: {error}");
-                        let mut next_err = error.source();
-                        while next_err.is_some(){
-                            error!("caused by: {:#}", next_err.unwrap());
-                            next_err = next_err.unwrap().source();
-                        }
-                    },
-                };
-                let thanks_message = format!("Gracias por tu idea @{}", nickname);
-                match mastodon.post(&thanks_message, Some(new_last_id.to_string())).await{
-                    Ok(response) => debug!("Mastodon post: {response}"),
-                    Err(error) => {
-                        error!("Feedback response: {error}");
-                        let mut next_err = error.source();
-                        while next_err.is_some(){
-                            error!("caused by: {:#}", next_err.unwrap());
-                            next_err = next_err.unwrap().source();
-                        }
-                    },
-                };
-                let mm_message = format!("Src: Mastodon. From: @{}. Content: {}", &nickname, &parse_html(content));
-                let html_message = format!(
-                    "<h6>Src: Mastodon</h6><ul><li>Id: {}</li><li>From: @{}</li><li>Content:</li></ul>{}",
-                    &new_last_id,
-                    &nickname,
-                    &content
-                );
-                debug!("Response: {:?}", matrix.post_message(room_id, &mm_message, &html_message).await);
-                zinc.publish(&json!([{
-                    "src": "Mastodon",
-                    "type": "idea",
-                    "from": format!("@{}", &nickname),
-                    "message": &parse_html(content),
-                }])).await.unwrap();
-            }else if let Some(message) = check_key("pregunta", content){
-                let feedback = Feedback::new("pregunta", &new_last_id, &message, name, nickname, 0, "Mastodon");
-                match feedback.post(url, token).await{
-                    Ok(response) => debug!("Feedback response: {response}"),
-                    Err(error) => {
-                        error!("Feedback response: {error}");
-                        let mut next_err = error.source();
-                        while next_err.is_some(){
-                            error!("caused by: {:#}", next_err.unwrap());
-                            next_err = next_err.unwrap().source();
-                        }
-                    },
-                };
-                let thanks_message = format!("Gracias por tu pregunta @{}", nickname);
-                match mastodon.post(&thanks_message, Some(new_last_id.to_string())).await{
-                    Ok(response) => debug!("Mastodon post: {response}"),
-                    Err(error) => {
-                        error!("Feedback response: {error}");
-                        let mut next_err = error.source();
-                        while next_err.is_some(){
-                            error!("caused by: {:#}", next_err.unwrap());
-                            next_err = next_err.unwrap().source();
-                        }
-                    },
-                };
-                let mm_message = format!("Src: Mastodon. From: @{}. Content: {}", &nickname, &parse_html(&content));
-                let html_message = format!(
-                    "<h6>Src: Mastodon</h6><ul><li>Id: {}</li><li>From: @{}</li><li>Content:</li></ul>{}",
-                    &new_last_id,
-                    &nickname,
-                    &content
-                );
-                debug!("Response: {:?}", matrix.post_message(room_id, &mm_message, &html_message).await);
-                zinc.publish(&json!([{
-                    "src": "Mastodon",
-                    "type": "pregunta",
-                    "from": format!("@{}", &nickname),
-                    "message": &parse_html(&content),
-                }])).await.unwrap();
-            }else if let Some(option) = check_comment("comentario", content){
-                let (commentario, _reference) = option;
-                if let Some(message) = commentario{
-                    let feedback = Feedback::new("comentario", &new_last_id, &message, name, nickname, 0, "Mastodon");
-                    match feedback.post(url, token).await{
-                        Ok(response) => debug!("Feedback response: {response}"),
-                        Err(error) => {
-                            error!("Feedback response: {error}");
-                            let mut next_err = error.source();
-                            while next_err.is_some(){
-                                error!("caused by: {:#}", next_err.unwrap());
-                                next_err = next_err.unwrap().source();
-                            }
-                        },
-                    };
-                    let thanks_message = format!("Gracias por tu comentario @{}", nickname);
-                    match mastodon.post(&thanks_message, Some(new_last_id.to_string())).await{
-                        Ok(response) => debug!("Mastodon post: {response}"),
-                        Err(error) => {
-                            error!("Feedback response: {error}");
-                            let mut next_err = error.source();
-                            while next_err.is_some(){
-                                error!("caused by: {:#}", next_err.unwrap());
-                                next_err = next_err.unwrap().source();
-                            }
-                        },
-                    };
-                    let mm_message = format!("Src: Mastodon. From: @{}. Content: {}", &nickname, &parse_html(content));
-                    let html_message = format!(
-                        "<h6>Src: Mastodon</h6><ul><li>Id: {}</li><li>From: @{}</li><li>Content:</li></ul>{}",
-                        &new_last_id,
-                        &nickname,
-                        &content
-                    );
-                    debug!("Response: {:?}", matrix.post_message(room_id, &mm_message, &html_message).await);
-                    match zinc.publish(&json!([{
-                        "src": "Mastodon",
-                        "type": "comentario",
-                        "from": format!("@{}", &nickname),
-                        "message": &parse_html(content),
-                    }])).await{
-                            Ok(response) => debug!("Response: {response}"),
-                            Err(error) => {
-                                error!("Feedback response: {error}");
-                                let mut next_err = error.source();
-                                while next_err.is_some(){
-                                    error!("caused by: {:#}", next_err.unwrap());
-                                    next_err = next_err.unwrap().source();
-                                }
-                            },
-                        }
-                }
-            }else{
-                let feedback = Feedback::new("mencion", &new_last_id, content, name, nickname, 0, "Mastodon");
-                match feedback.post(url, token).await{
-                    Ok(response) => debug!("Feedback response: {response}"),
-                    Err(error) => {
-                        error!("Feedback response: {error}");
-                        let mut next_err = error.source();
-                        while next_err.is_some(){
-                            error!("caused by: {:#}", next_err.unwrap());
-                            next_err = next_err.unwrap().source();
-                        }
-                    },
-                };
-                let mm_message = format!("Src: Mastodon. From: @{}. Content: {}", &nickname, &parse_html(&content));
-                let html_message = format!(
-                    "<h6>Src: Mastodon</h6><ul><li>Id: {}</li><li>From: @{}</li><li>Content:</li></ul>{}",
-                    &new_last_id,
-                    &nickname,
-                    &content
-                );
-                debug!("Response: {:?}", matrix.post_message(&room_id, &mm_message, &html_message).await);
-                match zinc.publish(&json!([{
-                    "src": "Mastodon",
-                    "type": "mencion",
-                    "from": format!("@{}", &nickname),
-                    "message": &parse_html(&content),
-                }])).await{
-                        Ok(res) => debug!("{}", res.text().await.unwrap()),
-                        Err(e) => error!("Error: {:?}", e),
+        for notification in mentions_reversed {
+            let mention = match Mention::from_notification(&notification){
+                Some(mention) => mention,
+                None => {
+                    error!("Unexpected notification: {}", notification);
+                    continue;
+                },
+            };
+            let handled = match notification.get("type").and_then(|v| v.as_str()){
+                Some("update") => edit(watchdog, account, &mention).await,
+                _ => handle(watchdog, account, &mention).await,
+            };
+            // Nothing moves past a pending notification, so it is retried
+            pending = pending || !handled;
+            if pending{
+                continue;
+            }
+            new_last_id = mention.id.to_string();
+            match acknowledge {
+                Acknowledge::Dismiss => {
+                    if let Err(error) = account.mastodon.dismiss_notification(&mention.id).await{
+                        log_error("Mastodon dismiss", &error);
                     }
+                },
+                Acknowledge::Marker => read_id = Some(mention.id.to_string()),
+                _ => {},
             }
         }
-    }else{
-        zinc.publish(&json!([{
-            "src": "Mastodon",
-            "type": "search",
-            "message": "Something goes wrong!!",
-        }])).await.unwrap();
+        // Other notifications, which may be the only ones read, move the
+        // cursor too
+        if let Some(max_id) = max_id.filter(|_| !pending){
+            if compare_ids(&max_id, since_id).is_gt(){
+                if acknowledge == Acknowledge::Marker{
+                    read_id = Some(max_id.to_string());
+                }
+                new_last_id = max_id;
+            }
+        }
+    }else if let Err(error) = res{
+        log_error("Mastodon notifications", &error);
+        watchdog.zinc.push(&Event::poll_error(&error.to_string()).to_value());
+    }
+    if let Some(read_id) = read_id.filter(|read_id| Some(read_id) != marker.as_ref()){
+        if let Err(error) = account.mastodon.set_notifications_marker(&read_id).await{
+            log_error("Mastodon marker", &error);
+        }
     }
-    if new_last_id != "" && new_last_id != last_id{
+    if !new_last_id.is_empty() && new_last_id != last_id{
         return Some(new_last_id);
     }
     None
 }
+
+// Edited statuses that were never processed are handled as new mentions
+async fn edit(watchdog: &Watchdog, account: &Account, mention: &Mention) -> bool{
+    match watchdog.store.get_status(&account.profile.name, &mention.status_id){
+        Ok(Some(record)) => revise(watchdog, account, &record, mention).await,
+        Ok(None) => handle(watchdog, account, mention).await,
+        Err(error) => {
+            log_error("Store", &error);
+            false
+        },
+    }
+}
+
+// Looks for edits and deletions of the statuses processed in the last days,
+// as Mastodon only notifies the edits of statuses it was asked to. To keep
+// within the rate limits only a batch is checked each time, starting at the
+// offset, and the offset of the next batch is returned.
+async fn recheck(watchdog: &Watchdog, offset: usize) -> usize{
+    let filter = Filter{from: Some(Utc::now() - Duration::days(watchdog.recheck_days)), ..Default::default()};
+    let records: Vec<Record> = match watchdog.store.records(&filter){
+        Ok(records) => records.into_iter().filter(|record| record.state != RETRACTED).collect(),
+        Err(error) => {
+            log_error("Store", &error);
+            return offset;
+        },
+    };
+    // Records come and go, so an offset past the end starts over
+    let offset = if offset < records.len() {offset} else {0};
+    let batch = &records[offset..records.len().min(offset + watchdog.recheck_batch)];
+    for record in batch{
+        // Profiles removed since the mention was processed are left alone
+        let account = match watchdog.accounts.iter().find(|account| account.profile.name == record.profile){
+            Some(account) => account,
+            None => continue,
+        };
+        match account.mastodon.status(&record.status_id).await{
+            Ok(response) => {
+                let mention = serde_json::from_str::<Value>(&response).ok()
+                    .and_then(|status| Mention::from_status(&status));
+                if let Some(mention) = mention{
+                    revise(watchdog, account, record, &mention).await;
+                }
+            },
+            Err(error) if is_not_found(&error) => retract(watchdog, record).await,
+            Err(error) => log_error("Mastodon status", &error),
+        }
+    }
+    offset + batch.len()
+}
+
+// Classifies an edited status again and updates the sinks that got it.
+// Returns whether every sink got the edit.
+#[instrument(skip_all, fields(notification_id = %record.notification_id, status_id = %record.status_id))]
+async fn revise(watchdog: &Watchdog, account: &Account, record: &Record, mention: &Mention) -> bool{
+    if mention.edited_at.is_none() || mention.edited_at == record.edited_at{
+        return true;
+    }
+    let context = get_context(account, mention).await;
+    let (category, message) = match account.profile.classify_in_thread(&mention.content, context.get_root_text()){
+        Some(classified) => classified,
+        None => {
+            debug!("Edit without message: {}", mention.content);
+            return true;
+        },
+    };
+    // The sinks know the mention by the notification that was processed
+    let mention = Mention{id: record.notification_id.to_string(), ..mention.clone()};
+    let text = parse_html(&mention.content);
+    let mut deliveries = Vec::new();
+    if let Some(feedback_id) = &record.feedback_id{
+        let response = watchdog.feedback.update(feedback_id, &json!({"category": category, "content": message})).await;
+        if let Err(error) = &response{
+            log_error("Feedback edit", error);
+        }
+        deliveries.push(Delivery::new("feedback", &response).with_remote_id(Some(feedback_id.to_string())));
+    }
+    if let Some(event_id) = get_remote_id(watchdog, record, "matrix"){
+        let response = watchdog.matrix.edit_message(&watchdog.room_id, &event_id, &mention.get_text(),
+            &mention.get_html(account.mastodon.get_base_uri())).await;
+        debug!("Response: {:?}", response);
+        deliveries.push(Delivery::new("matrix", &response).with_remote_id(Some(event_id)));
+    }
+    let delivered = deliveries.iter().all(|delivery| delivery.delivered);
+    if let Err(error) = watchdog.store.revise(&record.profile, &record.notification_id, &mention, &category, &text){
+        log_error("Store", &error);
+    }
+    watchdog.zinc.push(&Event::edit(&mention, &category, &text, deliveries).to_value());
+    delivered
+}
+
+// Withdraws a deleted status from the sinks that got it
+#[instrument(skip_all, fields(notification_id = %record.notification_id, status_id = %record.status_id))]
+async fn retract(watchdog: &Watchdog, record: &Record){
+    let mut deliveries = Vec::new();
+    if let Some(feedback_id) = &record.feedback_id{
+        let response = watchdog.feedback.delete(feedback_id).await;
+        if let Err(error) = &response{
+            log_error("Feedback delete", error);
+        }
+        deliveries.push(Delivery::new("feedback", &response).with_remote_id(Some(feedback_id.to_string())));
+    }
+    if let Some(event_id) = get_remote_id(watchdog, record, "matrix"){
+        let response = watchdog.matrix.redact(&watchdog.room_id, &event_id, "Borrado por su autor").await;
+        debug!("Response: {:?}", response);
+        deliveries.push(Delivery::new("matrix", &response).with_remote_id(Some(event_id)));
+    }
+    if let Err(error) = watchdog.store.set_state(&record.profile, &record.notification_id, RETRACTED){
+        log_error("Store", &error);
+    }
+    watchdog.zinc.push(&Event::retraction(record, deliveries).to_value());
+}
+
+fn get_remote_id(watchdog: &Watchdog, record: &Record, sink: &str) -> Option<String>{
+    match watchdog.store.get_remote_id(&record.profile, &record.notification_id, sink){
+        Ok(remote_id) => remote_id,
+        Err(error) => {
+            log_error("Store", &error);
+            None
+        },
+    }
+}
+
+fn is_not_found(error: &Error) -> bool{
+    error.downcast_ref::<reqwest::Error>()
+        .and_then(|error| error.status())
+        .is_some_and(|status| status == reqwest::StatusCode::NOT_FOUND)
+}
+
+// Id of the account that owns the token. Some software sends it as a number.
+async fn get_account_id(mastodon: &Mastodon) -> Option<String>{
+    let id = mastodon.verify_credentials().await
+        .and_then(|response| Ok(serde_json::from_str::<Value>(&response)?))
+        .map(|account| account.get("id").map(|id| id.as_str().map(|id| id.to_string()).unwrap_or(id.to_string())));
+    match id{
+        Ok(id) => id,
+        Err(error) => {
+            log_error(&format!("Account of {}", mastodon.get_base_uri()), &error);
+            None
+        },
+    }
+}
+
+// Software of the instance, Mastodon when it can not be detected
+async fn detect_software(mastodon: &Mastodon) -> Software{
+    match mastodon.detect_software().await{
+        Ok(software) => software,
+        Err(error) => {
+            log_error(&format!("Software of {}", mastodon.get_base_uri()), &error);
+            Software::Mastodon
+        },
+    }
+}
+
+// Read marker of the notifications, when the account has one
+async fn get_marker(account: &Account) -> Option<String>{
+    match account.mastodon.notifications_marker().await {
+        Ok(marker) => marker,
+        Err(error) => {
+            log_error("Mastodon marker", &error);
+            None
+        },
+    }
+}
+
+// Either the notification or its status, found by a search, was recorded.
+// Pending notifications are retried.
+fn is_processed(store: &Store, profile: &str, mention: &Mention) -> Result<bool, Error>{
+    if store.contains(profile, &mention.id)?{
+        return Ok(true);
+    }
+    Ok(store.get(profile, &mention.id)?.is_none() && store.contains_status(profile, &mention.status_id)?)
+}
+
+// Classifies and processes a mention, unless its status was already
+// processed. Returns whether every sink got the mention.
+async fn handle(watchdog: &Watchdog, account: &Account, mention: &Mention) -> bool{
+    match is_processed(&watchdog.store, &account.profile.name, mention){
+        Ok(true) => {
+            debug!("Already processed: {} ({})", mention.id, mention.status_id);
+            return true;
+        },
+        Ok(false) => {},
+        Err(error) => log_error("Store", &error),
+    }
+    let content = mention.content.as_str();
+    debug!("==========");
+    debug!("Text: {}", parse_html(content));
+    debug!("Id: {}", mention.id);
+    debug!("created_at: {}", mention.created_at);
+    debug!("Name: {}", mention.name);
+    debug!("Screen Name: {}", mention.nickname);
+    let context = get_context(account, mention).await;
+    if let Some((category, message)) = account.profile.classify_in_thread(content, context.get_root_text()){
+        let thanks_message = account.profile.get_thanks(&category, &mention.nickname);
+        return process(watchdog, account, mention, &category, &message, thanks_message, context).await;
+    }
+    true
+}
+
+// Copy of the mention with the location of its archived attachments. The
+// attachments that can not be archived are only linked.
+async fn archive(watchdog: &Watchdog, mention: &Mention) -> Mention{
+    let mut mention = mention.clone();
+    let media = match &watchdog.media{
+        Some(media) => media,
+        None => return mention,
+    };
+    for (index, attachment) in mention.media.iter_mut().enumerate(){
+        match media.archive(&watchdog.matrix, &mention.status_id, index, attachment).await{
+            Ok(archived) => {
+                debug!("Archived {} in {}", attachment.url, archived);
+                attachment.archived = Some(archived);
+            },
+            Err(error) => log_error("Media archive", &error),
+        }
+    }
+    mention
+}
+
+// Statuses above a reply in its thread, if they are still available
+async fn get_context(account: &Account, mention: &Mention) -> Context{
+    let in_reply_to_id = match &mention.in_reply_to_id{
+        Some(in_reply_to_id) => in_reply_to_id,
+        None => return Context::default(),
+    };
+    match account.mastodon.context(&mention.status_id).await {
+        Ok(response) => serde_json::from_str::<Value>(&response)
+            .map(|context| Context::from_context(&context, in_reply_to_id))
+            .unwrap_or_default(),
+        Err(error) => {
+            log_error("Mastodon context", &error);
+            Context::default()
+        },
+    }
+}
+
+#[instrument(skip_all, fields(notification_id = %mention.id, status_id = %mention.status_id, category = %category))]
+async fn process(watchdog: &Watchdog, account: &Account, mention: &Mention,
+        category: &str, message: &str, thanks_message: Option<String>, context: Context) -> bool{
+    let profile = &account.profile;
+    // A pending mention is only sent to the sinks that failed
+    let done = watchdog.store.delivered(&profile.name, &mention.id).unwrap_or_else(|error| {
+        log_error("Store", &error);
+        Vec::new()
+    });
+    let routes = |sink: &str| profile.routes(sink) && !done.iter().any(|done| done == sink);
+    // The archived attachments are only shown in Matrix, so a retry that does
+    // not post it again does not archive them again
+    let mention = &if routes("matrix"){
+        archive(watchdog, mention).await
+    }else{
+        mention.clone()
+    };
+    let content = mention.content.as_str();
+    let nickname = mention.nickname.as_str();
+    let mut deliveries = Vec::new();
+    if routes("feedback"){
+        let reference = qualify(&profile.name, &mention.id);
+        let mut feedback = Feedback::new(category, &reference, message, &mention.name, nickname, 0, "Mastodon");
+        if watchdog.feedback_format >= 2{
+            feedback = feedback.with_metadata(Metadata::new(mention, context));
+        }
+        let response = watchdog.feedback.post(&feedback).await;
+        match &response{
+            Ok(created) => debug!("Feedback {} created for status {}", created.id, mention.status_id),
+            Err(error) => log_error("Feedback response", error),
+        };
+        let remote_id = response.as_ref().ok().map(|created| created.id.to_string());
+        deliveries.push(Delivery::new("feedback", &response.map(|created| created.id)).with_remote_id(remote_id));
+    }
+    let text = parse_html(content);
+    let fields = get_fields(mention, category, &text);
+    for webhook in watchdog.webhooks.iter().filter(|webhook| webhook.accepts(category)){
+        let sink = format!("webhook:{}", webhook.name);
+        if !routes(&sink){
+            continue;
+        }
+        let response = webhook.send(&watchdog.webhook_sink, &fields).await;
+        match &response{
+            Ok(response) => debug!("Webhook {} response: {response}", webhook.name),
+            Err(error) => log_error(&format!("Webhook {}", webhook.name), error),
+        };
+        deliveries.push(Delivery::new(&sink, &response));
+    }
+    let mut reply_id = None;
+    let mastodon = &account.mastodon;
+    let status_id = mention.status_id.as_str();
+    let action = profile.get_action(category);
+    let response = match (action, thanks_message) {
+        _ if !routes("mastodon") => None,
+        (Action::Reply, Some(thanks_message)) => Some(mastodon.post(&thanks_message, Some(status_id.to_string())).await),
+        (Action::Favourite, _) => Some(mastodon.favourite(status_id).await),
+        (Action::Bookmark, _) => Some(mastodon.bookmark(status_id).await),
+        (Action::Boost, _) => Some(mastodon.reblog(status_id).await),
+        _ => None,
+    };
+    if let Some(response) = response{
+        match &response{
+            Ok(response) => {
+                debug!("Mastodon {:?}: {response}", action);
+                // Other actions answer with the status of the mention
+                if action == Action::Reply{
+                    reply_id = serde_json::from_str::<Value>(response).ok()
+                        .and_then(|value| value.get("id")?.as_str().map(|id| id.to_string()));
+                }
+            },
+            Err(error) => log_error("Mastodon response", error),
+        };
+        deliveries.push(Delivery::new("mastodon", &response).with_remote_id(reply_id.clone()));
+    }
+    let mut event_id = None;
+    let mut parent = None;
+    if routes("matrix"){
+        let mm_message = mention.get_text();
+        let html_message = mention.get_html(account.mastodon.get_base_uri());
+        parent = mention.in_reply_to_id.as_ref()
+            .and_then(|in_reply_to_id| watchdog.threads.lock().unwrap().get(&qualify(&profile.name, in_reply_to_id)).cloned());
+        let response = match &parent{
+            Some(thread) => watchdog.matrix.post_thread_message(&watchdog.room_id, thread, &mm_message, &html_message).await,
+            None => watchdog.matrix.post_message(&watchdog.room_id, &mm_message, &html_message).await,
+        };
+        debug!("Response: {:?}", response);
+        event_id = response.as_ref().ok().and_then(|response| Matrix::get_event_id(response));
+        deliveries.push(Delivery::new("matrix", &response).with_remote_id(event_id.clone()));
+    }
+    let delivered = deliveries.iter().all(|delivery| delivery.delivered);
+    let recorded = match watchdog.store.record(&profile.name, mention, category, &text, reply_id.as_deref(), &deliveries){
+        Ok(()) => true,
+        Err(error) => {
+            log_error("Store", &error);
+            false
+        },
+    };
+    watchdog.zinc.push(&Event::mention(mention, category, &text, deliveries).to_value());
+    if let Some(event_id) = event_id{
+        let thread = Thread{
+            root: parent.map(|thread| thread.root).unwrap_or(event_id.to_string()),
+            last: event_id.to_string(),
+        };
+        {
+            let mut threads = watchdog.threads.lock().unwrap();
+            threads.insert(&qualify(&profile.name, &mention.status_id), thread.clone());
+            if let Some(reply_id) = reply_id{
+                threads.insert(&qualify(&profile.name, &reply_id), thread);
+            }
+            debug!("Save: {:?}", threads.save(THREADS_FILENAME));
+        }
+        let reaction = if delivered {"✅"} else {"⚠️"};
+        debug!("Response: {:?}", watchdog.matrix.send_reaction(&watchdog.room_id, &event_id, reaction).await);
+    }else if delivered && done.iter().any(|sink| sink == "matrix"){
+        // Posted by an earlier attempt, and now the rest of the sinks got it
+        if let Some(event_id) = watchdog.store.get_remote_id(&profile.name, &mention.id, "matrix").ok().flatten(){
+            debug!("Response: {:?}", watchdog.matrix.send_reaction(&watchdog.room_id, &event_id, "✅").await);
+        }
+    }
+    delivered && recorded
+}
@@ -0,0 +1,85 @@
+#[derive(Debug, PartialEq)]
+pub enum Command{
+    Reply{id: String, text: String},
+    Approve{id: String},
+    Reject{id: String},
+    Recategorize{id: String, category: String},
+}
+
+impl Command{
+    pub fn parse(message: &str) -> Option<Command>{
+        let message = message.trim();
+        if !message.starts_with('!'){
+            return None;
+        }
+        let mut parts = message[1..].splitn(3, char::is_whitespace);
+        let name = parts.next()?.to_lowercase();
+        let id = parts.next()?.trim().to_string();
+        if id.is_empty(){
+            return None;
+        }
+        let rest = parts.next().map(|s| s.trim().to_string()).unwrap_or_default();
+        match name.as_str() {
+            "reply" if !rest.is_empty() => Some(Command::Reply{id, text: rest}),
+            "approve" => Some(Command::Approve{id}),
+            "reject" => Some(Command::Reject{id}),
+            // The categories depend on the profile of the mention
+            "recategorize" => {
+                let category = rest.trim_start_matches('#').to_lowercase();
+                if !category.is_empty() && !category.contains(char::is_whitespace){
+                    Some(Command::Recategorize{id, category})
+                }else{
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &str{
+        match self {
+            Command::Reply{..} => "reply",
+            Command::Approve{..} => "approve",
+            Command::Reject{..} => "reject",
+            Command::Recategorize{..} => "recategorize",
+        }
+    }
+
+    pub fn get_id(&self) -> &str{
+        match self {
+            Command::Reply{id, ..} => id,
+            Command::Approve{id} => id,
+            Command::Reject{id} => id,
+            Command::Recategorize{id, ..} => id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::Command;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("!reply 123 Gracias por la idea"),
+            Some(Command::Reply{id: "123".to_string(), text: "Gracias por la idea".to_string()}));
+        assert_eq!(Command::parse("!approve 123"),
+            Some(Command::Approve{id: "123".to_string()}));
+        assert_eq!(Command::parse(" !Reject 123 "),
+            Some(Command::Reject{id: "123".to_string()}));
+        assert_eq!(Command::parse("!recategorize 123 #pregunta"),
+            Some(Command::Recategorize{id: "123".to_string(), category: "pregunta".to_string()}));
+        assert_eq!(Command::parse("!recategorize podcast/123 Sugerencia"),
+            Some(Command::Recategorize{id: "podcast/123".to_string(), category: "sugerencia".to_string()}));
+    }
+
+    #[test]
+    fn parse_invalid_commands() {
+        assert_eq!(Command::parse("approve 123"), None);
+        assert_eq!(Command::parse("!approve"), None);
+        assert_eq!(Command::parse("!reply 123"), None);
+        assert_eq!(Command::parse("!recategorize 123"), None);
+        assert_eq!(Command::parse("!recategorize 123 una receta"), None);
+        assert_eq!(Command::parse("!delete 123"), None);
+    }
+}
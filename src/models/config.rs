@@ -1,17 +1,31 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::fs;
 use tracing::{debug, info};
+use super::profile::DEFAULT_PROFILE;
 use super::Error;
 
 #[derive(Deserialize, Serialize)]
 pub struct Config{
     pub last_id: String,
+    #[serde(default)]
+    pub matrix_since: Option<String>,
+    #[serde(default)]
+    pub last_digest: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub feedback_since: Option<String>,
+    // Last notification id of every profile but the default one, which
+    // keeps using `last_id`
+    #[serde(default)]
+    pub cursors: BTreeMap<String, String>,
 }
 
 impl Config {
     pub fn new(last_id: &str) -> Self{
-        Config { last_id: last_id.to_string() }
+        Config { last_id: last_id.to_string(), matrix_since: None, last_digest: None, feedback_since: None,
+            cursors: BTreeMap::new() }
     }
 
     pub fn read(filename: &str) -> Result<Config, Error>{
@@ -34,7 +48,35 @@ impl Config {
         fs::write(filename, toml)
     }
 
-    pub fn get_last_id(&self) -> &str{
-        &self.last_id
+    pub fn get_cursor(&self, profile: &str) -> &str{
+        if profile == DEFAULT_PROFILE{
+            return &self.last_id;
+        }
+        self.cursors.get(profile).map(|cursor| cursor.as_str()).unwrap_or("0")
+    }
+
+    pub fn set_cursor(&mut self, profile: &str, cursor: &str){
+        if profile == DEFAULT_PROFILE{
+            self.last_id = cursor.to_string();
+        }else{
+            self.cursors.insert(profile.to_string(), cursor.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::Config;
+
+    #[test]
+    fn cursors() {
+        let mut config = Config::new("100");
+        assert_eq!(config.get_cursor("default"), "100");
+        assert_eq!(config.get_cursor("podcast"), "0");
+        config.set_cursor("podcast", "7");
+        config.set_cursor("default", "101");
+        assert_eq!(config.last_id, "101");
+        let config: Config = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(config.get_cursor("podcast"), "7");
     }
 }
@@ -0,0 +1,195 @@
+use chrono::{DateTime, Local, Utc};
+use cron::Schedule;
+use regex::Regex;
+use std::str::FromStr;
+use super::html::{escape, is_web_url};
+use super::stats::Item;
+use super::{Error, Stats};
+
+// Mastodon rejects statuses longer than this by default
+pub const MAX_TOOT_LENGTH: usize = 500;
+
+// Summary of the feedback received in a period, posted on a schedule
+pub struct Digest{
+    stats: Stats,
+}
+
+pub fn parse_schedule(expression: &str) -> Result<Schedule, Error>{
+    Ok(Schedule::from_str(expression)?)
+}
+
+// Next time the digest is due after `last`, in local time
+pub fn next_run(schedule: &Schedule, last: DateTime<Utc>) -> Option<DateTime<Utc>>{
+    schedule.after(&last.with_timezone(&Local))
+        .next()
+        .map(|next| next.with_timezone(&Utc))
+}
+
+fn item_to_html(item: &Item) -> String{
+    match &item.status_url {
+        Some(url) if is_web_url(url) => format!("<li><a href=\"{}\">{}</a>: {}</li>",
+            escape(url), escape(&item.account), escape(&item.message)),
+        _ => format!("<li>{}: {}</li>", escape(&item.account), escape(&item.message)),
+    }
+}
+
+// Text of the markdown links without the `@` of mentions and the `#` of
+// hashtags, so a toot neither mentions the accounts nor carries the hashtags
+fn unmention(text: &str) -> String{
+    let link = Regex::new(r"\[([^\]]*)\]\([^)]*\)").unwrap();
+    let prefix = Regex::new(r"(^|[^\w/])[@#]+").unwrap();
+    prefix.replace_all(&link.replace_all(text, "$1"), "$1").to_string()
+}
+
+// Toots credit the authors without mentioning them
+fn item_to_text(item: &Item, toot: bool) -> String{
+    let (account, message) = if toot {
+        (item.account.trim_start_matches('@').to_string(), unmention(&item.message))
+    }else{
+        (item.account.to_string(), item.message.to_string())
+    };
+    let mut text = format!("- {}: {}", account, message.replace('\n', " "));
+    if let Some(url) = &item.status_url{
+        text.push_str(&format!(" {}", url));
+    }
+    text
+}
+
+impl Digest{
+    pub fn new(stats: Stats) -> Self{
+        Self { stats }
+    }
+
+    fn get_title(&self) -> String{
+        format!("Resumen del {} al {}",
+            self.stats.from.with_timezone(&Local).format("%d/%m/%Y"),
+            self.stats.to.with_timezone(&Local).format("%d/%m/%Y"))
+    }
+
+    // Toots leave out the hashtags, or the watchdog would find them as
+    // feedback
+    fn get_counts(&self, toot: bool) -> Vec<String>{
+        let prefix = if toot {""} else {"#"};
+        self.stats.per_category().iter()
+            .map(|(category, total)| format!("{}{}: {}", prefix, category, total))
+            .collect()
+    }
+
+    fn get_text(&self, toot: bool) -> String{
+        let mut text = self.get_title();
+        text.push_str(&format!("\n\n{}", self.get_counts(toot).join(", ")));
+        if !self.stats.ideas.is_empty(){
+            text.push_str("\n\nIdeas:");
+            for idea in self.stats.ideas.iter(){
+                text.push_str(&format!("\n{}", item_to_text(idea, toot)));
+            }
+        }
+        if !self.stats.unanswered.is_empty(){
+            text.push_str("\n\nPreguntas sin responder:");
+            for question in self.stats.unanswered.iter(){
+                text.push_str(&format!("\n{}", item_to_text(question, toot)));
+            }
+        }
+        text
+    }
+
+    pub fn to_text(&self) -> String{
+        self.get_text(false)
+    }
+
+    pub fn to_html(&self) -> String{
+        let counts: String = self.get_counts(false).iter()
+            .map(|count| format!("<li>{}</li>", escape(count)))
+            .collect();
+        let mut html = format!("<h5>{}</h5><ul>{}</ul>", escape(&self.get_title()), counts);
+        if !self.stats.ideas.is_empty(){
+            let ideas: String = self.stats.ideas.iter().map(item_to_html).collect();
+            html.push_str(&format!("<p>Ideas:</p><ol>{}</ol>", ideas));
+        }
+        if !self.stats.unanswered.is_empty(){
+            let questions: String = self.stats.unanswered.iter().map(item_to_html).collect();
+            html.push_str(&format!("<p>Preguntas sin responder:</p><ul>{}</ul>", questions));
+        }
+        html
+    }
+
+    // Splits the digest in statuses short enough to be posted as a thread
+    pub fn to_toots(&self, max_length: usize) -> Vec<String>{
+        let mut toots: Vec<String> = Vec::new();
+        let mut current = String::new();
+        for line in self.get_text(true).lines(){
+            let line: String = line.chars().take(max_length).collect();
+            let length = current.chars().count() + line.chars().count() + 1;
+            if !current.is_empty() && length > max_length{
+                toots.push(current.trim_end().to_string());
+                current = String::new();
+            }
+            current.push_str(&line);
+            current.push('\n');
+        }
+        if !current.trim().is_empty(){
+            toots.push(current.trim_end().to_string());
+        }
+        toots
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{next_run, parse_schedule, Digest};
+    use crate::models::Stats;
+    use chrono::{Duration, TimeZone, Utc};
+    use serde_json::json;
+
+    fn get_digest() -> Digest{
+        let from = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2023, 7, 8, 12, 0, 0).unwrap();
+        let per_week = vec![
+            json!({"week": "2023-06-26", "category": "idea", "total": 1}),
+            json!({"week": "2023-07-03", "category": "idea", "total": 2}),
+            json!({"week": "2023-07-03", "category": "pregunta", "total": 1}),
+        ];
+        let questions: Vec<_> = (0..20)
+            .map(|i| json!({"notification_id": i.to_string(), "account": "@a",
+                "message": "¿Cuándo sale el próximo episodio del podcast?",
+                "status_url": format!("https://mastodon.social/@a/{}", i)}))
+            .collect();
+        let ideas = vec![
+            json!({"notification_id": "100", "account": "@b", "message": "<b>Hablar de Rust</b>"}),
+            json!({"notification_id": "101", "account": "@c", "message": concat!(
+                "[@atareao](https://mastodon.social/@atareao) [#idea](https://mastodon.social/tags/idea) ",
+                "invitar a [@foo](https://mastodon.social/@foo) o a @bar@fosstodon.org #podcast")}),
+        ];
+        Digest::new(Stats::from_hits(from, to, &per_week, &[], &questions, &[], &ideas))
+    }
+
+    #[test]
+    fn to_html() {
+        let html = get_digest().to_html();
+        assert!(html.contains("<li>#idea: 3</li><li>#pregunta: 1</li>"));
+        assert!(html.contains("<li>@b: &lt;b&gt;Hablar de Rust&lt;/b&gt;</li>"));
+    }
+
+    #[test]
+    fn to_toots() {
+        let toots = get_digest().to_toots(500);
+        assert!(toots.len() > 1);
+        assert!(toots.iter().all(|toot| toot.chars().count() <= 500));
+        assert!(toots[0].starts_with("Resumen del "));
+        assert!(toots[0].contains("idea: 3, pregunta: 1"));
+        assert!(toots.iter().all(|toot| !toot.contains('#') && !toot.contains("- @")));
+        assert!(toots.iter().any(|toot| toot.contains("- c: atareao idea invitar a foo o a bar@fosstodon.org podcast")));
+        assert!(get_digest().to_text().contains("[@foo](https://mastodon.social/@foo)"));
+        assert!(get_digest().to_text().contains("#idea: 3"));
+    }
+
+    #[test]
+    fn schedule() {
+        let schedule = parse_schedule("0 0 9 * * Mon *").unwrap();
+        let last = Utc.with_ymd_and_hms(2023, 7, 3, 12, 0, 0).unwrap();
+        let next = next_run(&schedule, last).unwrap();
+        assert!(next > last);
+        assert!(next - last <= Duration::days(7));
+        assert!(parse_schedule("every monday").is_err());
+    }
+}
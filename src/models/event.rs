@@ -0,0 +1,228 @@
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use super::mention::Attachment;
+use super::store::Record;
+use super::Mention;
+
+/// Version of the record layout, bumped on every incompatible change
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Kind of record published to Zinc
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType{
+    /// Mastodon was polled for new notifications
+    Poll,
+    /// Mastodon could not be polled
+    PollError,
+    /// A mention was classified and delivered to the sinks
+    Mention,
+    /// A moderator command from Matrix was executed
+    Command,
+    /// The author edited a mention, that was delivered again to the sinks
+    Edit,
+    /// The author deleted a mention, that was withdrawn from the sinks
+    Retraction,
+}
+
+/// Outcome of delivering a mention to one sink
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Delivery{
+    /// Sink name: `feedback`, `mastodon`, `matrix` or `webhook:<name>`
+    pub sink: String,
+    pub delivered: bool,
+    /// Error returned by the sink when it was not delivered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Id assigned by the sink, like the feedback id or the Matrix event id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_id: Option<String>,
+}
+
+/// Record published to Zinc for everything the watchdog does
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Event{
+    pub schema_version: u32,
+    /// Source of the record, always `Mastodon`
+    pub src: String,
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    /// When the record was produced, RFC 3339
+    pub timestamp: String,
+    /// When the record was produced, in microseconds, used by Zinc as the
+    /// time of the record
+    #[serde(rename = "_timestamp", default)]
+    pub zinc_timestamp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notification_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_url: Option<String>,
+    /// When the status was created, RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    /// Account of the author, as `@user@instance`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// `idea`, `pregunta`, `comentario` or `mencion`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<String>,
+    /// Plain text of the status, or the error for `poll_error`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Moderator command: `reply`, `approve`, `reject` or `recategorize`, or
+    /// `notify_applied`/`notify_answered` when the author was told about it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Number of notifications received by a `poll`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deliveries: Vec<Delivery>,
+}
+
+impl Event{
+    pub fn new(event_type: EventType) -> Self{
+        let now = Utc::now();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            src: "Mastodon".to_string(),
+            event_type,
+            timestamp: now.to_rfc3339(),
+            zinc_timestamp: now.timestamp_micros(),
+            notification_id: None,
+            status_id: None,
+            status_url: None,
+            created_at: None,
+            account: None,
+            category: None,
+            language: None,
+            visibility: None,
+            message: None,
+            command: None,
+            count: None,
+            attachments: Vec::new(),
+            deliveries: Vec::new(),
+        }
+    }
+
+    pub fn poll(count: usize) -> Self{
+        Self {
+            count: Some(count),
+            ..Self::new(EventType::Poll)
+        }
+    }
+
+    pub fn poll_error(error: &str) -> Self{
+        Self {
+            message: Some(error.to_string()),
+            ..Self::new(EventType::PollError)
+        }
+    }
+
+    pub fn edit(mention: &Mention, category: &str, message: &str, deliveries: Vec<Delivery>) -> Self{
+        Self {
+            event_type: EventType::Edit,
+            ..Self::mention(mention, category, message, deliveries)
+        }
+    }
+
+    pub fn retraction(record: &Record, deliveries: Vec<Delivery>) -> Self{
+        Self {
+            notification_id: Some(record.notification_id.to_string()),
+            status_id: Some(record.status_id.to_string()),
+            status_url: record.status_url.clone(),
+            created_at: Some(record.created_at.to_string()),
+            account: Some(record.account.to_string()),
+            category: Some(record.category.to_string()),
+            deliveries,
+            ..Self::new(EventType::Retraction)
+        }
+    }
+
+    pub fn mention(mention: &Mention, category: &str, message: &str, deliveries: Vec<Delivery>) -> Self{
+        Self {
+            notification_id: Some(mention.id.to_string()),
+            status_id: Some(mention.status_id.to_string()),
+            status_url: mention.url.clone(),
+            created_at: Some(mention.created_at.to_string()),
+            account: Some(format!("@{}", mention.nickname)),
+            category: Some(category.to_string()),
+            language: mention.language.clone(),
+            visibility: mention.visibility.clone(),
+            message: Some(message.to_string()),
+            attachments: mention.media.clone(),
+            deliveries,
+            ..Self::new(EventType::Mention)
+        }
+    }
+
+    pub fn command(command: &str, notification_id: &str, sink: &str, result: &Result<String, String>) -> Self{
+        Self {
+            notification_id: Some(notification_id.to_string()),
+            command: Some(command.to_string()),
+            deliveries: vec![Delivery::new(sink, result)],
+            ..Self::new(EventType::Command)
+        }
+    }
+
+    pub fn to_value(&self) -> Value{
+        serde_json::to_value(self).unwrap()
+    }
+
+    pub fn json_schema() -> String{
+        serde_json::to_string_pretty(&schemars::schema_for!(Event)).unwrap()
+    }
+}
+
+impl Delivery{
+    pub fn new<T: ToString>(sink: &str, result: &Result<String, T>) -> Self{
+        Self {
+            sink: sink.to_string(),
+            delivered: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            remote_id: None,
+        }
+    }
+
+    pub fn with_remote_id(self, remote_id: Option<String>) -> Self{
+        Self { remote_id, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{Event, EventType, SCHEMA_VERSION};
+    use serde_json::Value;
+
+    #[test]
+    fn serialize_poll() {
+        let value = Event::poll(3).to_value();
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        assert_eq!(value["type"], "poll");
+        assert_eq!(value["count"], 3);
+        let timestamp = chrono::DateTime::parse_from_rfc3339(value["timestamp"].as_str().unwrap()).unwrap();
+        assert_eq!(value["_timestamp"], timestamp.timestamp_micros());
+        assert!(value.get("notification_id").is_none());
+        let event: Event = serde_json::from_value(value).unwrap();
+        assert_eq!(event.event_type, EventType::Poll);
+    }
+
+    #[test]
+    fn json_schema() {
+        let schema: Value = serde_json::from_str(&Event::json_schema()).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        for property in ["schema_version", "type", "timestamp", "notification_id", "deliveries"]{
+            assert!(properties.contains_key(property), "{property}");
+        }
+    }
+}
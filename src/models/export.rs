@@ -0,0 +1,135 @@
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use super::store::Record;
+use super::Error;
+
+const CSV_COLUMNS: [&str; 12] = ["notification_id", "profile", "status_id", "status_url", "account", "category",
+    "message", "feedback_id", "reply_id", "state", "created_at", "processed_at"];
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format{
+    Csv,
+    Jsonl,
+    Markdown,
+}
+
+fn csv_field(value: &str) -> String{
+    if value.contains([',', '"', '\n', '\r']){
+        format!("\"{}\"", value.replace('"', "\"\""))
+    }else{
+        value.to_string()
+    }
+}
+
+fn to_csv(records: &[Record]) -> String{
+    let mut csv = format!("{}\n", CSV_COLUMNS.join(","));
+    for record in records.iter(){
+        let fields = [
+            record.notification_id.as_str(),
+            record.profile.as_str(),
+            record.status_id.as_str(),
+            record.status_url.as_deref().unwrap_or_default(),
+            record.account.as_str(),
+            record.category.as_str(),
+            record.message.as_str(),
+            record.feedback_id.as_deref().unwrap_or_default(),
+            record.reply_id.as_deref().unwrap_or_default(),
+            record.state.as_str(),
+            record.created_at.as_str(),
+            record.processed_at.as_str(),
+        ];
+        let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&format!("{}\n", line.join(",")));
+    }
+    csv
+}
+
+fn to_jsonl(records: &[Record]) -> Result<String, Error>{
+    let mut jsonl = String::new();
+    for record in records.iter(){
+        jsonl.push_str(&format!("{}\n", serde_json::to_string(record)?));
+    }
+    Ok(jsonl)
+}
+
+// One section per category, with the items in the order they came in
+fn to_markdown(records: &[Record], from: Option<NaiveDate>, to: Option<NaiveDate>) -> String{
+    let from = from.map(|from| from.format("%Y-%m-%d").to_string())
+        .or(records.first().map(|record| record.processed_at.chars().take(10).collect()))
+        .unwrap_or_default();
+    let to = to.map(|to| to.format("%Y-%m-%d").to_string())
+        .or(records.last().map(|record| record.processed_at.chars().take(10).collect()))
+        .unwrap_or_default();
+    let mut markdown = format!("# Feedback del {} al {}\n", from, to);
+    let mut categories: Vec<&str> = Vec::new();
+    for record in records.iter(){
+        if !categories.contains(&record.category.as_str()){
+            categories.push(&record.category);
+        }
+    }
+    categories.sort();
+    for category in categories{
+        markdown.push_str(&format!("\n## {}\n\n", category));
+        for record in records.iter().filter(|record| record.category == category){
+            let date: String = record.processed_at.chars().take(10).collect();
+            let account = match &record.status_url {
+                Some(url) => format!("[{}]({})", record.account, url),
+                None => record.account.to_string(),
+            };
+            markdown.push_str(&format!("- {} {}: {}\n", date, account, record.message.replace('\n', " ")));
+        }
+    }
+    markdown
+}
+
+// The period is only used in the title of the Markdown document
+pub fn export(records: &[Record], format: Format, from: Option<NaiveDate>,
+        to: Option<NaiveDate>) -> Result<String, Error>{
+    match format {
+        Format::Csv => Ok(to_csv(records)),
+        Format::Jsonl => to_jsonl(records),
+        Format::Markdown => Ok(to_markdown(records, from, to)),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{export, Format};
+    use crate::models::store::Record;
+
+    fn get_record(id: &str, category: &str, message: &str) -> Record{
+        Record{
+            notification_id: id.to_string(),
+            profile: "default".to_string(),
+            status_id: format!("9{}", id),
+            status_url: Some(format!("https://mastodon.social/@a/9{}", id)),
+            account: "@a".to_string(),
+            category: category.to_string(),
+            message: message.to_string(),
+            feedback_id: None,
+            reply_id: None,
+            state: "received".to_string(),
+            created_at: "2023-07-25T10:00:00.000Z".to_string(),
+            processed_at: format!("2023-07-2{}T10:00:05Z", id),
+            edited_at: None,
+        }
+    }
+
+    #[test]
+    fn formats() {
+        let records = vec![
+            get_record("1", "pregunta", "¿Uno, \"dos\"?"),
+            get_record("2", "idea", "Hablar\nde Rust"),
+        ];
+        let csv = export(&records, Format::Csv, None, None).unwrap();
+        assert!(csv.starts_with("notification_id,profile,status_id,"));
+        assert!(csv.contains(",\"¿Uno, \"\"dos\"\"?\","));
+        assert!(csv.contains(",\"Hablar\nde Rust\","));
+        let jsonl = export(&records, Format::Jsonl, None, None).unwrap();
+        assert_eq!(jsonl.lines().count(), 2);
+        let markdown = export(&records, Format::Markdown, None, None).unwrap();
+        assert_eq!(markdown, "# Feedback del 2023-07-21 al 2023-07-22\n\
+            \n## idea\n\n- 2023-07-22 [@a](https://mastodon.social/@a/92): Hablar de Rust\n\
+            \n## pregunta\n\n- 2023-07-21 [@a](https://mastodon.social/@a/91): ¿Uno, \"dos\"?\n");
+    }
+}
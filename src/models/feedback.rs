@@ -1,7 +1,13 @@
-use serde::{Serialize, Deserialize};
-use reqwest::Client;
+use chrono::Utc;
+use mastodon_watchdog::signature::{sign, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde_json::Value;
+use reqwest::Method;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use std::time::Duration;
 use tracing::debug;
-use super::Error;
+use super::metadata::FORMAT_VERSION;
+use super::{Error, HttpSink, Metadata};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Feedback{
@@ -12,6 +18,51 @@ pub struct Feedback{
     pub nickname: String,
     pub applied: i64,
     pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format_version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+}
+
+// Feedback created by the remote service
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Created{
+    #[serde(deserialize_with = "deserialize_id")]
+    pub id: String,
+}
+
+// The service may return the id either as a number or as a string
+fn deserialize_id<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::String(id) => Ok(id),
+        Value::Number(id) => Ok(id.to_string()),
+        other => Err(serde::de::Error::custom(format!("invalid id: {}", other))),
+    }
+}
+
+// Feedback whose state changed in the remote service
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Change{
+    #[serde(deserialize_with = "deserialize_id")]
+    pub id: String,
+    // Id of the Mastodon notification, as sent in `Feedback.reference`
+    pub reference: String,
+    pub category: String,
+    pub applied: i64,
+    #[serde(default)]
+    pub episode: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+pub struct FeedbackSink{
+    url: String,
+    token: String,
+    secret: Option<String>,
+    sink: HttpSink,
 }
 
 impl Feedback{
@@ -24,19 +75,205 @@ impl Feedback{
             username: username.to_string(),
             nickname: nickname.to_string(),
             applied,
-            source: source.to_string(), 
+            source: source.to_string(),
+            format_version: None,
+            metadata: None,
         }
 
     }
-    pub async fn post(&self, url: &str, token: &str) -> Result<String, Error>{
-        debug!("post: {url}");
-        Ok(Client::new()
-            .post(url)
-            .header(reqwest::header::AUTHORIZATION, format!("Bearer {}", &token))
-            .json(self)
-            .send()
-            .await?
-            .text()
-            .await?)
+
+    // Moves the payload to the versioned format that carries the metadata
+    pub fn with_metadata(self, metadata: Metadata) -> Self{
+        Self {
+            format_version: Some(FORMAT_VERSION),
+            metadata: Some(metadata),
+            ..self
+        }
+    }
+}
+
+impl FeedbackSink{
+    pub fn new(url: &str, token: &str, retries: u32, connect_timeout: Duration, timeout: Duration) -> Self{
+        Self {
+            url: url.to_string(),
+            token: token.to_string(),
+            secret: None,
+            sink: HttpSink::new(retries, connect_timeout, timeout),
+        }
+    }
+
+    // Signs every request body with HMAC-SHA256 so the service can verify it
+    pub fn with_secret(self, secret: Option<String>) -> Self{
+        Self { secret, ..self }
+    }
+
+    fn get_headers(&self, reference: Option<&str>, body: &[u8]) -> HeaderMap{
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", self.token)).unwrap());
+        // Lets the service discard a retried request it already processed
+        if let Some(Ok(key)) = reference.map(|reference| HeaderValue::from_str(&format!("mastodon-{}", reference))){
+            headers.insert("Idempotency-Key", key);
+        }
+        if let Some(secret) = &self.secret{
+            let timestamp = Utc::now().timestamp();
+            headers.insert(TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+            headers.insert(SIGNATURE_HEADER,
+                HeaderValue::from_str(&sign(secret.as_bytes(), timestamp, body)).unwrap());
+        }
+        headers
+    }
+
+    pub async fn post(&self, feedback: &Feedback) -> Result<Created, Error>{
+        debug!("post: {}", self.url);
+        let body = serde_json::to_vec(feedback)?;
+        let response = self.sink.send(Method::POST, &self.url, self.get_headers(Some(&feedback.reference), &body), body).await?;
+        serde_json::from_str(&response)
+            .map_err(|e| format!("Unexpected feedback response {}: {}", response, e).into())
+    }
+
+    // Feedback applied (or answered, for questions) since the given time
+    pub async fn changes(&self, since: Option<&str>) -> Result<Vec<Change>, Error>{
+        debug!("changes: {}", self.url);
+        let mut url = reqwest::Url::parse(&self.url)?;
+        url.query_pairs_mut().append_pair("applied", "1");
+        if let Some(since) = since{
+            url.query_pairs_mut().append_pair("updated_since", since);
+        }
+        let response = self.sink.send(Method::GET, url.as_str(), self.get_headers(None, b""), Vec::new()).await?;
+        serde_json::from_str(&response)
+            .map_err(|e| format!("Unexpected feedback changes {}: {}", response, e).into())
+    }
+
+    pub async fn update(&self, reference: &str, changes: &Value) -> Result<String, Error>{
+        let url = format!("{}/{}", self.url.trim_end_matches('/'), reference);
+        debug!("update: {url}");
+        let body = serde_json::to_vec(changes)?;
+        // Every change of an item would share the key, so only creations have one
+        self.sink.send(Method::PATCH, &url, self.get_headers(None, &body), body).await
+    }
+
+    // Removes feedback whose status was deleted by its author
+    pub async fn delete(&self, reference: &str) -> Result<String, Error>{
+        let url = format!("{}/{}", self.url.trim_end_matches('/'), reference);
+        debug!("delete: {url}");
+        self.sink.send(Method::DELETE, &url, self.get_headers(None, b""), Vec::new()).await
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{Created, Feedback, FeedbackSink};
+    use crate::models::fixtures::get_mention;
+    use crate::models::{Context, Metadata};
+    use mastodon_watchdog::signature::{verify, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+    use serde_json::json;
+    use std::time::Duration;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn parse_created() {
+        let created: Created = serde_json::from_str(r#"{"id": 42, "category": "idea"}"#).unwrap();
+        assert_eq!(created.id, "42");
+        let created: Created = serde_json::from_str(r#"{"id": "abc"}"#).unwrap();
+        assert_eq!(created.id, "abc");
+    }
+
+    #[test]
+    fn payload_versions() {
+        let feedback = Feedback::new("idea", "123", "#idea", "atareao", "atareao", 0, "Mastodon");
+        assert_eq!(serde_json::to_value(&feedback).unwrap(), json!({
+            "category": "idea", "reference": "123", "content": "#idea", "username": "atareao",
+            "nickname": "atareao", "applied": 0, "source": "Mastodon"
+        }));
+        let mention = get_mention("123", json!({"id": "456", "content": "<p>#idea</p>"}));
+        let value = serde_json::to_value(feedback.with_metadata(Metadata::new(&mention, Context::default()))).unwrap();
+        assert_eq!(value["format_version"], 2);
+        assert_eq!(value["reference"], "123");
+        assert_eq!(value["metadata"]["status_id"], "456");
+        assert_eq!(value["metadata"]["content_text"], "#idea");
+    }
+
+    #[tokio::test]
+    async fn post() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/feedback"))
+            .and(header("Authorization", "Bearer token"))
+            .and(header("Idempotency-Key", "mastodon-123"))
+            .respond_with(ResponseTemplate::new(201).set_body_string(r#"{"id": 42}"#))
+            .mount(&server)
+            .await;
+        let sink = FeedbackSink::new(&format!("{}/feedback", server.uri()), "token", 0,
+            Duration::from_secs(1), Duration::from_secs(5));
+        let feedback = Feedback::new("idea", "123", "#idea", "atareao", "atareao", 0, "Mastodon");
+        assert_eq!(sink.post(&feedback).await.unwrap().id, "42");
+    }
+
+    #[tokio::test]
+    async fn changes() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("applied", "1"))
+            .and(query_param("updated_since", "2023-07-25T10:00:00Z"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"[{"id": 42, "reference": "123", "category": "idea", "applied": 1, "episode": "512"}]"#))
+            .mount(&server)
+            .await;
+        let sink = FeedbackSink::new(&server.uri(), "token", 0, Duration::from_secs(1), Duration::from_secs(5));
+        let changes = sink.changes(Some("2023-07-25T10:00:00Z")).await.unwrap();
+        assert_eq!(changes[0].id, "42");
+        assert_eq!(changes[0].episode.as_deref(), Some("512"));
+        assert_eq!(changes[0].updated_at, None);
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path("/feedback/42"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let url = format!("{}/feedback/", server.uri());
+        let sink = FeedbackSink::new(&url, "token", 0, Duration::from_secs(1), Duration::from_secs(5));
+        sink.delete("42").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn update() {
+        let server = MockServer::start().await;
+        Mock::given(method("PATCH"))
+            .and(path("/feedback/42"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let url = format!("{}/feedback/", server.uri());
+        let sink = FeedbackSink::new(&url, "token", 0, Duration::from_secs(1), Duration::from_secs(5));
+        sink.update("42", &json!({"applied": 1})).await.unwrap();
+        sink.update("42", &json!({"category": "pregunta"})).await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests.iter().all(|request| request.headers.get("Idempotency-Key").is_none()));
+    }
+
+    #[tokio::test]
+    async fn post_signed() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(201).set_body_string(r#"{"id": 42}"#))
+            .mount(&server)
+            .await;
+        let sink = FeedbackSink::new(&server.uri(), "token", 0, Duration::from_secs(1), Duration::from_secs(5))
+            .with_secret(Some("secret".to_string()));
+        let feedback = Feedback::new("idea", "123", "#idea", "atareao", "atareao", 0, "Mastodon");
+        sink.post(&feedback).await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        let timestamp: i64 = requests[0].headers.get(TIMESTAMP_HEADER).unwrap()
+            .to_str().unwrap().parse().unwrap();
+        let signature = requests[0].headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap();
+        assert_eq!(verify(b"secret", timestamp, &requests[0].body, signature, timestamp, 300), Ok(()));
     }
 }
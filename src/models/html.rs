@@ -0,0 +1,108 @@
+use ammonia::Builder;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+// Subset of HTML that Matrix clients are expected to render
+// https://spec.matrix.org/latest/client-server-api/#mroommessage-msgtypes
+const TAGS: [&str; 34] = ["font", "del", "h1", "h2", "h3", "h4", "h5", "h6",
+    "blockquote", "p", "a", "ul", "ol", "sup", "sub", "li", "b", "i", "u",
+    "strong", "em", "strike", "code", "hr", "br", "div", "table", "thead",
+    "tbody", "tr", "th", "td", "caption", "pre"];
+const URL_SCHEMES: [&str; 5] = ["https", "http", "ftp", "mailto", "magnet"];
+
+pub fn sanitize(html: &str, links: HashMap<String, String>) -> String{
+    let tag_attributes = HashMap::from([
+        ("a", HashSet::from(["href"])),
+        ("font", HashSet::from(["data-mx-bg-color", "data-mx-color", "color"])),
+        ("ol", HashSet::from(["start"])),
+    ]);
+    Builder::empty()
+        .tags(HashSet::from(TAGS))
+        .add_tags(["span"])
+        .tag_attributes(tag_attributes)
+        .url_schemes(HashSet::from(URL_SCHEMES))
+        .link_rel(None)
+        .strip_comments(true)
+        .attribute_filter(move |element, attribute, value| {
+            match (element, attribute, links.get(value)) {
+                ("a", "href", Some(link)) => Some(Cow::Owned(link.to_string())),
+                _ => Some(Cow::Borrowed(value)),
+            }
+        })
+        .clean(html)
+        .to_string()
+}
+
+pub fn escape(text: &str) -> String{
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars(){
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Plain text rendering of a status, keeping paragraphs and line breaks
+pub fn to_plain_text(html: &str) -> String{
+    let breaks = Regex::new(r"(?i)<br\s*/?>").unwrap();
+    let paragraphs = Regex::new(r"(?i)</p>\s*<p[^>]*>").unwrap();
+    let tags = Regex::new(r"<[^>]*>").unwrap();
+    let text = breaks.replace_all(html, "\n");
+    let text = paragraphs.replace_all(&text, "\n\n");
+    let text = tags.replace_all(&text, "");
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+pub fn is_web_url(url: &str) -> bool{
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{escape, sanitize, to_plain_text};
+    use std::collections::HashMap;
+
+    #[test]
+    fn sanitize_html() {
+        let html = r#"<p onclick="alert(1)">Hola <script>alert(1)</script><img src="https://evil.com/x.png"><a href="javascript:alert(1)">link</a></p>"#;
+        assert_eq!(sanitize(html, HashMap::new()), "<p>Hola <a>link</a></p>");
+    }
+
+    #[test]
+    fn rewrite_links() {
+        let html = r#"<p><span class="h-card"><a href="https://mastodon.social/@atareao" class="u-url mention">@<span>atareao</span></a></span> <a href="https://mastodon.social/tags/idea" class="mention hashtag" rel="tag">#<span>idea</span></a></p>"#;
+        let links = HashMap::from([
+            ("https://mastodon.social/@atareao".to_string(), "https://mastodon.example/@atareao@mastodon.social".to_string()),
+            ("https://mastodon.social/tags/idea".to_string(), "https://mastodon.example/tags/idea".to_string()),
+        ]);
+        assert_eq!(sanitize(html, links),
+            r#"<p><span><a href="https://mastodon.example/@atareao@mastodon.social">@<span>atareao</span></a></span> <a href="https://mastodon.example/tags/idea">#<span>idea</span></a></p>"#);
+    }
+
+    #[test]
+    fn plain_text() {
+        let html = r#"<p>Hola &amp; <a href="https://example.com/tags/idea">#<span>idea</span></a><br>otra línea</p><p>&lt;fin&gt;</p>"#;
+        assert_eq!(to_plain_text(html), "Hola & #idea\notra línea\n\n<fin>");
+    }
+
+    #[test]
+    fn escape_text() {
+        assert_eq!(escape(r#"<b>"Tom" & 'Jerry'</b>"#),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;");
+    }
+}
@@ -0,0 +1,143 @@
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::fs;
+use tracing::{debug, info};
+use super::feedback::Change;
+use super::{compare_ids, unqualify, Error};
+
+const MAX_NOTIFIED: usize = 5000;
+
+// Messages sent to the author when their feedback changes. Text between
+// square brackets is only kept when every `{{field}}` in it has a value, like
+// "Tu idea se ha aplicado[ en el episodio {{episode}}]".
+pub struct Templates{
+    pub applied: String,
+    pub answered: String,
+}
+
+// Feedback already notified to its author, by the reference qualified with
+// its profile, with the state that was notified
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Notified{
+    #[serde(default)]
+    references: BTreeMap<String, String>,
+}
+
+// Ideas are applied and questions are answered. The authors of any other
+// category are not notified.
+pub fn get_state(change: &Change) -> Option<&'static str>{
+    match change.category.as_str() {
+        "idea" => Some("applied"),
+        "pregunta" => Some("answered"),
+        _ => None,
+    }
+}
+
+impl Templates{
+    pub fn get(&self, state: &str) -> &str{
+        match state {
+            "answered" => &self.answered,
+            _ => &self.applied,
+        }
+    }
+
+    pub fn render(&self, change: &Change) -> Option<String>{
+        let template = self.get(get_state(change)?);
+        let fields = [
+            ("category", Some(change.category.as_str())),
+            ("episode", change.episode.as_deref()),
+        ];
+        let field = Regex::new(r"\{\{(\w+)\}\}").unwrap();
+        let optional = Regex::new(r"\[([^\]]*)\]").unwrap();
+        let get = |name: &str| fields.iter()
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| *value);
+        let text = optional.replace_all(template, |captures: &Captures| {
+            let section = &captures[1];
+            if field.captures_iter(section).all(|field| get(&field[1]).is_some()){
+                section.to_string()
+            }else{
+                "".to_string()
+            }
+        });
+        Some(field.replace_all(&text, |captures: &Captures| get(&captures[1]).unwrap_or("").to_string())
+            .to_string())
+    }
+}
+
+impl Notified{
+    pub fn read(filename: &str) -> Result<Notified, Error>{
+        info!("read");
+        if Path::new(filename).exists(){
+            let data = fs::read_to_string(filename)?;
+            debug!("{}", data);
+            Ok(toml::from_str(&data)?)
+        }else{
+            let notified = Self::default();
+            notified.save(filename)?;
+            Ok(notified)
+        }
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), std::io::Error>{
+        info!("save");
+        let toml = toml::to_string(&self).unwrap();
+        fs::write(filename, toml)
+    }
+
+    pub fn contains(&self, reference: &str, state: &str) -> bool{
+        self.references.get(reference).is_some_and(|notified| notified == state)
+    }
+
+    pub fn insert(&mut self, reference: &str, state: &str){
+        self.references.insert(reference.to_string(), state.to_string());
+        while self.references.len() > MAX_NOTIFIED{
+            let oldest = self.references.keys()
+                .min_by(|a, b| compare_ids(unqualify(a), unqualify(b)))
+                .cloned()
+                .unwrap();
+            self.references.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{Notified, Templates};
+    use crate::models::feedback::Change;
+
+    fn get_change(category: &str, episode: Option<&str>) -> Change{
+        Change{
+            id: "42".to_string(),
+            reference: "123".to_string(),
+            category: category.to_string(),
+            applied: 1,
+            episode: episode.map(|episode| episode.to_string()),
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn render() {
+        let templates = Templates{
+            applied: "Tu {{category}} se ha aplicado[ en el episodio {{episode}}]. ¡Gracias!".to_string(),
+            answered: "Tu pregunta se ha respondido[ en el episodio {{episode}}]".to_string(),
+        };
+        assert_eq!(templates.render(&get_change("idea", Some("512"))).unwrap(),
+            "Tu idea se ha aplicado en el episodio 512. ¡Gracias!");
+        assert_eq!(templates.render(&get_change("idea", None)).unwrap(), "Tu idea se ha aplicado. ¡Gracias!");
+        assert_eq!(templates.render(&get_change("pregunta", None)).unwrap(), "Tu pregunta se ha respondido");
+        assert_eq!(templates.render(&get_change("comentario", None)), None);
+    }
+
+    #[test]
+    fn notified() {
+        let mut notified = Notified::default();
+        notified.insert("123", "applied");
+        assert!(notified.contains("123", "applied"));
+        assert!(!notified.contains("123", "answered"));
+        assert!(!notified.contains("124", "applied"));
+    }
+}
@@ -1,18 +1,29 @@
 use reqwest::Client;
 use std::format;
 use serde::{Serialize, Deserialize};
-use tracing::{info, debug};
-use super::Error;
+use serde_json::Value;
+use tracing::{info, debug, warn};
+use super::software::get_max_id;
+use super::{Error, Software};
+
+// Pages of notifications read in a poll from instances that can not filter
+// them by type
+const MAX_PAGES: u32 = 10;
+const PAGE_LIMIT: &str = "40";
 
 pub struct Mastodon{
     base_uri: String,
     access_token: String,
+    software: Software,
 }
 
 #[derive(Serialize, Deserialize)]
 struct Message{
     status: String,
     in_reply_to_id: Option<String>,
+    // The default of the account when it is not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visibility: Option<String>,
 }
 
 impl Mastodon{
@@ -20,32 +31,88 @@ impl Mastodon{
         Mastodon {
             base_uri: base_uri.to_string(),
             access_token: access_token.to_string(),
+            software: Software::Mastodon,
+        }
+    }
+
+    // Responses of other software are normalized to the ones of Mastodon
+    pub fn with_software(self, software: Software) -> Self{
+        Self { software, ..self }
+    }
+
+    // Software of the instance, from nodeinfo or else from the version
+    pub async fn detect_software(&self) -> Result<Software, Error>{
+        let client = Client::new();
+        let url = format!("{}/.well-known/nodeinfo", self.base_uri);
+        debug!("{}", &url);
+        let nodeinfo: Result<Value, Error> = async {
+            let links: Value = client.get(url).send().await?.error_for_status()?.json().await?;
+            let href = links.get("links")
+                .and_then(|links| links.as_array())
+                .and_then(|links| links.iter()
+                    .filter(|link| link.get("rel").and_then(|v| v.as_str())
+                        .is_some_and(|rel| rel.starts_with("http://nodeinfo.diaspora.software/ns/schema/2.")))
+                    .filter_map(|link| link.get("href")?.as_str())
+                    .next_back())
+                .ok_or("Nodeinfo without schema 2")?;
+            debug!("{}", href);
+            Ok(client.get(href).send().await?.error_for_status()?.json().await?)
+        }.await;
+        match nodeinfo.map(|nodeinfo| nodeinfo.pointer("/software/name").and_then(|v| v.as_str()).map(|v| v.to_string())) {
+            Ok(Some(name)) => return name.parse(),
+            Ok(None) => debug!("Nodeinfo without software"),
+            Err(error) => debug!("Nodeinfo: {}", error),
         }
+        let url = format!("{}/api/v1/instance", self.base_uri);
+        debug!("{}", &url);
+        let instance: Value = client.get(url).send().await?.error_for_status()?.json().await?;
+        Ok(Software::from_version(instance.get("version").and_then(|v| v.as_str()).unwrap_or_default()))
+    }
+
+    fn normalize_statuses(&self, response: String) -> Result<String, Error>{
+        if self.software == Software::Mastodon{
+            return Ok(response);
+        }
+        Ok(self.software.normalize_statuses(serde_json::from_str(&response)?).to_string())
+    }
+
+    pub fn get_base_uri(&self) -> &str{
+        &self.base_uri
     }
 
     pub async fn post(&self, message: &str, in_reply_to_id: Option<String>) -> Result<String, Error>{
+        self.post_with_visibility(message, in_reply_to_id, None).await
+    }
+
+    pub async fn post_with_visibility(&self, message: &str, in_reply_to_id: Option<String>,
+            visibility: Option<&str>) -> Result<String, Error>{
         info!("post");
         let url = format!("{}/api/v1/statuses", self.base_uri);
         debug!("{}", &url);
-        let body = Message{status: message.to_string(), in_reply_to_id};
+        let body = Message{
+            status: message.to_string(),
+            in_reply_to_id,
+            visibility: visibility.map(|visibility| visibility.to_string()),
+        };
         Ok(Client::new()
             .post(&url)
             .json(&body)
             .header("Authorization", format!("Bearer {}", self.access_token))
             .send()
             .await?
+            .error_for_status()?
             .text()
             .await?)
     }
 
-    #[allow(unused)]
-    pub async fn search(&self, min_id: &str) -> Result<String, Error>{
+    pub async fn search(&self, query: &str, min_id: &str) -> Result<String, Error>{
         let url = format!("{}/api/v2/search/", self.base_uri);
         debug!("{}", &url);
         let params = [
             ("min_id", min_id),
-            ("q", "atareao"),
-            ("type", "statuses")
+            ("q", query),
+            ("type", "statuses"),
+            ("limit", "40"),
         ];
         let client = Client::new();
         let res = client
@@ -54,16 +121,24 @@ impl Mastodon{
             .header("Authorization", format!("Bearer {}", self.access_token))
             .send()
             .await?
+            .error_for_status()?
             .text()
             .await?;
-        Ok(res)
+        if self.software == Software::Mastodon{
+            return Ok(res);
+        }
+        let mut data: Value = serde_json::from_str(&res)?;
+        data["statuses"] = self.software.normalize_statuses(data["statuses"].take());
+        Ok(data.to_string())
     }
-    pub async fn notifications(&self, since_id: &str) -> Result<String, Error>{
-        let url = format!("{}/api/v1/notifications/", self.base_uri);
+
+    // Statuses of a timeline, like `tag/atareao` or `list/42`
+    pub async fn timeline(&self, path: &str, min_id: &str) -> Result<String, Error>{
+        let url = format!("{}/api/v1/timelines/{}", self.base_uri, path);
         debug!("{}", &url);
         let params = [
-            ("types[]", "mention"),
-            ("since_id", since_id)
+            ("min_id", min_id),
+            ("limit", "40"),
         ];
         let client = Client::new();
         let res = client
@@ -72,10 +147,202 @@ impl Mastodon{
             .header("Authorization", format!("Bearer {}", self.access_token))
             .send()
             .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        self.normalize_statuses(res)
+    }
+
+    // Newest id of the notifications that were read, which may be of no
+    // mention, and the mentions and updates among them
+    pub async fn notifications(&self, since_id: &str) -> Result<(Option<String>, String), Error>{
+        let url = format!("{}/api/v1/notifications/", self.base_uri);
+        debug!("{}", &url);
+        let client = Client::new();
+        if self.software.filters_types(){
+            // Updates are mentions edited by their author
+            let params = [("since_id", since_id), ("types[]", "mention"), ("types[]", "update")];
+            let res = client
+                .get(url)
+                .query(&params)
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .send()
+                .await?
+                .text()
+                .await?;
+            let max_id = serde_json::from_str(&res).ok().and_then(|value| get_max_id(&value));
+            return Ok((max_id, res));
+        }
+        // Every type of notification comes, so the mentions may be beyond the
+        // newest page. Pages are read forward from the cursor with `min_id`
+        // and the mentions are picked here.
+        let mut notifications = Vec::new();
+        let mut newest = None;
+        let mut min_id = since_id.to_string();
+        for page in 1..=MAX_PAGES{
+            // Without a cursor only the newest page is read
+            let cursor = if min_id == "0" {"since_id"} else {"min_id"};
+            let res: Value = client
+                .get(&url)
+                .query(&[(cursor, min_id.as_str()), ("limit", PAGE_LIMIT)])
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let max_id = get_max_id(&res);
+            notifications.extend(res.as_array().cloned().unwrap_or_default());
+            newest = max_id.clone().or(newest);
+            match max_id{
+                Some(max_id) if min_id != "0" => min_id = max_id,
+                _ => break,
+            }
+            if page == MAX_PAGES{
+                warn!("More than {} pages of notifications since {}", MAX_PAGES, since_id);
+            }
+        }
+        Ok((newest, self.software.normalize_notifications(Value::from(notifications)).to_string()))
+    }
+
+    pub async fn notification(&self, id: &str) -> Result<String, Error>{
+        let url = format!("{}/api/v1/notifications/{}", self.base_uri, id);
+        debug!("{}", &url);
+        let client = Client::new();
+        let res = client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn status(&self, id: &str) -> Result<String, Error>{
+        let url = format!("{}/api/v1/statuses/{}", self.base_uri, id);
+        debug!("{}", &url);
+        let client = Client::new();
+        let res = client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(res)
+    }
+
+    // Ancestors and descendants of a status in its thread
+    pub async fn context(&self, id: &str) -> Result<String, Error>{
+        let url = format!("{}/api/v1/statuses/{}/context", self.base_uri, id);
+        debug!("{}", &url);
+        let client = Client::new();
+        let res = client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await?
+            .error_for_status()?
             .text()
             .await?;
         Ok(res)
+    }
 
+    // Account that owns the token
+    pub async fn verify_credentials(&self) -> Result<String, Error>{
+        let url = format!("{}/api/v1/accounts/verify_credentials", self.base_uri);
+        debug!("{}", &url);
+        let client = Client::new();
+        let res = client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn favourite(&self, id: &str) -> Result<String, Error>{
+        self.status_action(id, "favourite").await
+    }
+
+    pub async fn bookmark(&self, id: &str) -> Result<String, Error>{
+        self.status_action(id, "bookmark").await
+    }
+
+    pub async fn reblog(&self, id: &str) -> Result<String, Error>{
+        self.status_action(id, "reblog").await
+    }
+
+    async fn status_action(&self, id: &str, action: &str) -> Result<String, Error>{
+        let url = format!("{}/api/v1/statuses/{}/{}", self.base_uri, id, action);
+        debug!("{}", &url);
+        let client = Client::new();
+        let res = client
+            .post(url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(res)
+    }
+
+    // Removes a single notification, once it has been handled
+    pub async fn dismiss_notification(&self, id: &str) -> Result<String, Error>{
+        let url = format!("{}/api/v1/notifications/{}/dismiss", self.base_uri, id);
+        debug!("{}", &url);
+        let client = Client::new();
+        let res = client
+            .post(url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(res)
+    }
+
+    // Last notification read, as shown by the Mastodon clients
+    pub async fn notifications_marker(&self) -> Result<Option<String>, Error>{
+        let url = format!("{}/api/v1/markers", self.base_uri);
+        debug!("{}", &url);
+        let client = Client::new();
+        let res: Value = client
+            .get(url)
+            .query(&[("timeline[]", "notifications")])
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(res.pointer("/notifications/last_read_id")
+            .and_then(|id| id.as_str())
+            .map(|id| id.to_string()))
+    }
+
+    pub async fn set_notifications_marker(&self, last_read_id: &str) -> Result<String, Error>{
+        let url = format!("{}/api/v1/markers", self.base_uri);
+        debug!("{}", &url);
+        let client = Client::new();
+        let res = client
+            .post(url)
+            .form(&[("notifications[last_read_id]", last_read_id)])
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(res)
     }
 
     #[allow(unused)]
@@ -99,7 +366,181 @@ impl Mastodon{
 #[cfg(test)]
 mod tests{
     use crate::Mastodon;
+    use crate::models::Software;
     use dotenv::dotenv;
+    use serde_json::Value;
+    use wiremock::matchers::{body_string, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn markers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/markers"))
+            .and(query_param("timeline[]", "notifications"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"notifications": {"last_read_id": "35098814", "version": 361}}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/markers"))
+            .and(body_string("notifications%5Blast_read_id%5D=35098815"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/notifications/35098815/dismiss"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let mastodon = Mastodon::new(&server.uri(), "token");
+        assert_eq!(mastodon.notifications_marker().await.unwrap().as_deref(), Some("35098814"));
+        mastodon.set_notifications_marker("35098815").await.unwrap();
+        mastodon.dismiss_notification("35098815").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn notifications_by_page() {
+        let server = MockServer::start().await;
+        for (min_id, page) in [
+            ("5", r#"[{"id": "7", "type": "favourite", "status": {"id": "70"}},
+                      {"id": "6", "type": "mention", "account": {"id": "1"}, "status": {"id": "60"}}]"#),
+            ("7", r#"[{"id": "8", "type": "mention", "account": {"id": "1"}, "status": {"id": "80"}}]"#),
+            ("8", "[]"),
+        ]{
+            Mock::given(method("GET"))
+                .and(path("/api/v1/notifications/"))
+                .and(query_param("min_id", min_id))
+                .respond_with(ResponseTemplate::new(200).set_body_string(page))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let mastodon = Mastodon::new(&server.uri(), "token").with_software(Software::Akkoma);
+        let (max_id, notifications) = mastodon.notifications("5").await.unwrap();
+        let notifications: Value = serde_json::from_str(&notifications).unwrap();
+        let ids: Vec<&str> = notifications.as_array().unwrap().iter()
+            .map(|notification| notification["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, vec!["8", "6"]);
+        assert_eq!(max_id.as_deref(), Some("8"));
+    }
+
+    #[tokio::test]
+    async fn notifications_without_mentions() {
+        let server = MockServer::start().await;
+        // More pages of favourites than a poll reads
+        for id in 100..=110{
+            Mock::given(method("GET"))
+                .and(path("/api/v1/notifications/"))
+                .and(query_param("min_id", id.to_string()))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                    r#"[{{"id": "{}", "type": "favourite", "status": {{"id": "1"}}}}]"#, id + 1)))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/api/v1/notifications/"))
+            .and(query_param("min_id", "111"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+            .mount(&server)
+            .await;
+        let mastodon = Mastodon::new(&server.uri(), "token").with_software(Software::GoToSocial);
+        let (max_id, notifications) = mastodon.notifications("100").await.unwrap();
+        assert_eq!(max_id.as_deref(), Some("110"));
+        assert_eq!(notifications, "[]");
+        let (max_id, notifications) = mastodon.notifications("110").await.unwrap();
+        assert_eq!(max_id.as_deref(), Some("111"));
+        assert_eq!(notifications, "[]");
+    }
+
+    #[tokio::test]
+    async fn detect_software() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/nodeinfo"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                r#"{{"links": [{{"rel": "http://nodeinfo.diaspora.software/ns/schema/2.0", "href": "{}/nodeinfo/2.0"}}]}}"#,
+                server.uri())))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/nodeinfo/2.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"version": "2.0", "software": {"name": "gotosocial", "version": "0.15.0"}}"#))
+            .mount(&server)
+            .await;
+        let mastodon = Mastodon::new(&server.uri(), "token");
+        assert_eq!(mastodon.detect_software().await.unwrap(), Software::GoToSocial);
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/instance"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"uri": "akkoma.example", "version": "2.7.2 (compatible; Akkoma 3.10.4)"}"#))
+            .mount(&server)
+            .await;
+        let mastodon = Mastodon::new(&server.uri(), "token");
+        assert_eq!(mastodon.detect_software().await.unwrap(), Software::Akkoma);
+    }
+
+    #[tokio::test]
+    async fn status_actions() {
+        let server = MockServer::start().await;
+        for action in ["favourite", "bookmark", "reblog"]{
+            Mock::given(method("POST"))
+                .and(path(format!("/api/v1/statuses/456/{}", action)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"id": "456"}"#))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let mastodon = Mastodon::new(&server.uri(), "token");
+        mastodon.favourite("456").await.unwrap();
+        mastodon.bookmark("456").await.unwrap();
+        mastodon.reblog("456").await.unwrap();
+        assert!(mastodon.favourite("457").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn post_rejected() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .respond_with(ResponseTemplate::new(422).set_body_string(r#"{"error": "Text too long"}"#))
+            .mount(&server)
+            .await;
+        let mastodon = Mastodon::new(&server.uri(), "token");
+        assert!(mastodon.post("Hola", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn search_rejected() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/search/"))
+            .respond_with(ResponseTemplate::new(401).set_body_string(r#"{"error": "The access token is invalid"}"#))
+            .mount(&server)
+            .await;
+        let mastodon = Mastodon::new(&server.uri(), "token");
+        assert!(mastodon.search("#atareao", "0").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn post_unlisted() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .and(body_string(r#"{"status":"Hola","in_reply_to_id":null,"visibility":"unlisted"}"#))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"id": "1"}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let mastodon = Mastodon::new(&server.uri(), "token");
+        mastodon.post_with_visibility("Hola", None, Some("unlisted")).await.unwrap();
+    }
 
     /*
     #[actix_rt::test]
@@ -122,7 +563,7 @@ mod tests{
         println!("{}", token);
         let mastodon = Mastodon::new(&base_uri, &token);
         let id = "110758642668166239";
-        let res = mastodon.search(id).await.unwrap();
+        let res = mastodon.search("atareao", id).await.unwrap();
         println!("{}", res);
     }
 
@@ -134,7 +575,7 @@ mod tests{
         println!("{}", base_uri);
         println!("{}", token);
         let mastodon = Mastodon::new(&base_uri, &token);
-        let res = mastodon.notifications("0").await.unwrap();
+        let (_, res) = mastodon.notifications("0").await.unwrap();
         println!("{}", res);
     }
     /*
@@ -1,17 +1,42 @@
 use serde_json::{json, Value};
-use reqwest::{Client, Response};
+use reqwest::Client;
 use reqwest::header::{HeaderMap, HeaderValue, HeaderName};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use urlencoding::encode;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::debug;
-use super::Error;
+use tracing::{debug, warn};
+use super::{Error, Thread};
+
+static TXN_COUNTER: AtomicU64 = AtomicU64::new(0);
+// Events of the timeline in a sync. With more the timeline is `limited` and
+// the rest are fetched by pages.
+const SYNC_LIMIT: u32 = 50;
+const MAX_PAGES: u32 = 10;
+
+#[derive(Debug, PartialEq)]
+pub struct RoomMessage{
+    pub event_id: String,
+    pub sender: String,
+    pub body: String,
+}
 
 pub struct Matrix{
     base_url: String,
     token: String,
 }
 
+fn to_messages(events: &[Value]) -> Vec<RoomMessage>{
+    events.iter()
+        .filter(|event| event.get("type").and_then(|v| v.as_str()) == Some("m.room.message"))
+        .filter_map(|event| Some(RoomMessage{
+            event_id: event.get("event_id")?.as_str()?.to_string(),
+            sender: event.get("sender")?.as_str()?.to_string(),
+            body: event.pointer("/content/body")?.as_str()?.to_string(),
+        }))
+        .collect()
+}
+
 impl Matrix{
     pub fn new(base_url: String, token: String) -> Self{
         Self {
@@ -20,29 +45,264 @@ impl Matrix{
         }
     }
 
+    pub fn get_room(&self, room_id: &str) -> String{
+        format!("{}:{}", room_id, self.base_url)
+    }
+
     pub async fn post_message(&self, room_id: &str, message: &str, html: &str) -> Result<String, Error>{
-        let room = encode(room_id);
-        let now = SystemTime::now();
-        let ts = now.duration_since(UNIX_EPOCH).expect("Time went backwrds").as_secs();
-        let url = format!(
-            "https://{}/_matrix/client/v3/rooms/{}:{}/send/m.room.message/{}",
-            self.base_url,
-            room,
-            self.base_url,
-            ts
-        );
         let body = json!({
             "msgtype": "m.text",
             "format": "org.matrix.custom.html",
             "body": message,
             "formatted_body": html
         });
+        self.send(room_id, "m.room.message", &body).await
+    }
+
+    pub async fn post_thread_message(&self, room_id: &str, thread: &Thread, message: &str, html: &str) -> Result<String, Error>{
+        let body = json!({
+            "msgtype": "m.text",
+            "format": "org.matrix.custom.html",
+            "body": message,
+            "formatted_body": html,
+            "m.relates_to": {
+                "rel_type": "m.thread",
+                "event_id": thread.root,
+                "is_falling_back": true,
+                "m.in_reply_to": {
+                    "event_id": thread.last
+                }
+            }
+        });
+        self.send(room_id, "m.room.message", &body).await
+    }
+
+    // Replaces the text of a message. Clients without edits show the fallback
+    // body, marked with an asterisk.
+    pub async fn edit_message(&self, room_id: &str, event_id: &str, message: &str, html: &str) -> Result<String, Error>{
+        let body = json!({
+            "msgtype": "m.text",
+            "format": "org.matrix.custom.html",
+            "body": format!("* {}", message),
+            "formatted_body": format!("* {}", html),
+            "m.new_content": {
+                "msgtype": "m.text",
+                "format": "org.matrix.custom.html",
+                "body": message,
+                "formatted_body": html
+            },
+            "m.relates_to": {
+                "rel_type": "m.replace",
+                "event_id": event_id
+            }
+        });
+        self.send(room_id, "m.room.message", &body).await
+    }
+
+    pub async fn redact(&self, room_id: &str, event_id: &str, reason: &str) -> Result<String, Error>{
+        let url = format!(
+            "https://{}/_matrix/client/v3/rooms/{}:{}/redact/{}/{}",
+            self.base_url,
+            encode(room_id),
+            self.base_url,
+            encode(event_id),
+            Self::txn_id()
+        );
         let mut header_map = HeaderMap::new();
         header_map.insert(HeaderName::from_str("Content-type").unwrap(),
                           HeaderValue::from_str("application/json").unwrap());
         header_map.append(HeaderName::from_str("Authorization").unwrap(),
                           HeaderValue::from_str(&format!("Bearer {}", self.token)).unwrap());
-        Self::put(&url, header_map, &body).await
+        Self::put(&url, header_map, &json!({"reason": reason})).await
+    }
+
+    // Uploads a file to the media repository and returns its `mxc://` uri
+    pub async fn upload(&self, content_type: &str, filename: &str, content: Vec<u8>) -> Result<String, Error>{
+        let url = format!("https://{}/_matrix/media/v3/upload", self.base_url);
+        debug!("URL: {}", url);
+        let response: Value = Client::new()
+            .post(&url)
+            .query(&[("filename", filename)])
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", content_type)
+            .body(content)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.get("content_uri")
+            .and_then(|v| v.as_str())
+            .ok_or(format!("Upload without content_uri: {}", response))?
+            .to_string())
+    }
+
+    pub async fn send_reaction(&self, room_id: &str, event_id: &str, key: &str) -> Result<String, Error>{
+        let body = json!({
+            "m.relates_to": {
+                "rel_type": "m.annotation",
+                "event_id": event_id,
+                "key": key
+            }
+        });
+        self.send(room_id, "m.reaction", &body).await
+    }
+
+    pub async fn sync(&self, room_id: &str, since: Option<&str>) -> Result<String, Error>{
+        let url = format!("https://{}/_matrix/client/v3/sync", self.base_url);
+        debug!("URL: {}", url);
+        let filter = json!({
+            "presence": {"types": []},
+            "account_data": {"types": []},
+            "room": {
+                "rooms": [self.get_room(room_id)],
+                "account_data": {"types": []},
+                "ephemeral": {"types": []},
+                "state": {"types": []},
+                "timeline": {"types": ["m.room.message"], "limit": SYNC_LIMIT}
+            }
+        }).to_string();
+        let mut params = vec![
+            ("filter", filter),
+            ("timeout", "0".to_string()),
+        ];
+        if let Some(since) = since{
+            params.push(("since", since.to_string()));
+        }
+        Ok(Client::new()
+            .get(&url)
+            .query(&params)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?
+            .text()
+            .await?)
+    }
+
+    pub fn get_event_id(response: &str) -> Option<String>{
+        let data: Value = serde_json::from_str(response).ok()?;
+        Some(data.get("event_id")?.as_str()?.to_string())
+    }
+
+    // Messages of the room since the last sync, oldest first, and the token
+    // of the next one. A `limited` timeline is completed with the messages
+    // between both syncs.
+    pub async fn get_messages(&self, room_id: &str, since: Option<&str>) -> Result<(String, Vec<RoomMessage>), Error>{
+        let response = self.sync(room_id, since).await?;
+        let (next_batch, mut messages) = self.parse_sync(room_id, &response)?;
+        let (since, mut from) = match (since, self.parse_gap(room_id, &response)){
+            (Some(since), Some(from)) => (since, from),
+            _ => return Ok((next_batch, messages)),
+        };
+        let mut older = Vec::new();
+        for page in 1..=MAX_PAGES{
+            let response = self.messages(room_id, &from, since).await?;
+            let (end, chunk) = Self::parse_messages(&response)?;
+            older.splice(0..0, chunk);
+            match end{
+                Some(end) => from = end,
+                None => break,
+            }
+            if page == MAX_PAGES{
+                warn!("More than {} pages of messages since the last sync", MAX_PAGES);
+            }
+        }
+        older.append(&mut messages);
+        Ok((next_batch, older))
+    }
+
+    // Older messages of the room, from the newest, until the `to` token
+    async fn messages(&self, room_id: &str, from: &str, to: &str) -> Result<String, Error>{
+        let url = format!("https://{}/_matrix/client/v3/rooms/{}/messages",
+            self.base_url, encode(&self.get_room(room_id)));
+        debug!("URL: {}", url);
+        let params = [
+            ("dir", "b".to_string()),
+            ("from", from.to_string()),
+            ("to", to.to_string()),
+            ("limit", SYNC_LIMIT.to_string()),
+            ("filter", json!({"types": ["m.room.message"]}).to_string()),
+        ];
+        Ok(Client::new()
+            .get(&url)
+            .query(&params)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?)
+    }
+
+    fn get_timeline<'a>(&self, room_id: &str, data: &'a Value) -> Option<&'a Value>{
+        data.pointer(&format!("/rooms/join/{}/timeline",
+            self.get_room(room_id).replace('~', "~0").replace('/', "~1")))
+    }
+
+    pub fn parse_sync(&self, room_id: &str, response: &str) -> Result<(String, Vec<RoomMessage>), Error>{
+        let data: Value = serde_json::from_str(response)?;
+        let next_batch = data.get("next_batch")
+            .and_then(|v| v.as_str())
+            .ok_or(format!("Sync without next_batch: {}", response))?
+            .to_string();
+        let events = self.get_timeline(room_id, &data)
+            .and_then(|timeline| timeline.get("events"))
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+        Ok((next_batch, to_messages(&events)))
+    }
+
+    // Token to page back from when the timeline of the sync left out events
+    pub fn parse_gap(&self, room_id: &str, response: &str) -> Option<String>{
+        let data: Value = serde_json::from_str(response).ok()?;
+        let timeline = self.get_timeline(room_id, &data)?;
+        if !timeline.get("limited").and_then(|v| v.as_bool()).unwrap_or_default(){
+            return None;
+        }
+        Some(timeline.get("prev_batch")?.as_str()?.to_string())
+    }
+
+    // Messages of a page, oldest first, and the token of the previous page
+    pub fn parse_messages(response: &str) -> Result<(Option<String>, Vec<RoomMessage>), Error>{
+        let data: Value = serde_json::from_str(response)?;
+        let mut events = data.get("chunk")
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+        // Without events the `to` token was reached
+        let end = data.get("end")
+            .and_then(|v| v.as_str())
+            .filter(|_| !events.is_empty())
+            .map(|end| end.to_string());
+        events.reverse();
+        Ok((end, to_messages(&events)))
+    }
+
+    async fn send(&self, room_id: &str, event_type: &str, body: &Value) -> Result<String, Error>{
+        let room = encode(room_id);
+        let url = format!(
+            "https://{}/_matrix/client/v3/rooms/{}:{}/send/{}/{}",
+            self.base_url,
+            room,
+            self.base_url,
+            event_type,
+            Self::txn_id()
+        );
+        let mut header_map = HeaderMap::new();
+        header_map.insert(HeaderName::from_str("Content-type").unwrap(),
+                          HeaderValue::from_str("application/json").unwrap());
+        header_map.append(HeaderName::from_str("Authorization").unwrap(),
+                          HeaderValue::from_str(&format!("Bearer {}", self.token)).unwrap());
+        Self::put(&url, header_map, body).await
+    }
+
+    // Transaction ids must be unique per access token, otherwise the
+    // homeserver silently drops events sent within the same second.
+    fn txn_id() -> String{
+        let now = SystemTime::now();
+        let ts = now.duration_since(UNIX_EPOCH).expect("Time went backwrds").as_millis();
+        format!("{}-{}", ts, TXN_COUNTER.fetch_add(1, Ordering::Relaxed))
     }
 
     #[allow(unused)]
@@ -85,6 +345,7 @@ impl Matrix{
             .body(content)
             .send()
             .await?
+            .error_for_status()?
             .text()
             .await?
         )
@@ -92,9 +353,55 @@ impl Matrix{
 }
 #[cfg(test)]
 mod tests{
-    use super::Matrix;
+    use super::{Matrix, RoomMessage};
     use dotenv::dotenv;
 
+    #[test]
+    fn parse_sync() {
+        let matrix_client = Matrix::new("matrix.example.com".to_string(), "token".to_string());
+        let response = r#"{
+            "next_batch": "s72595_4483_1934",
+            "rooms": {"join": {"!room:matrix.example.com": {"timeline": {"events": [
+                {"type": "m.room.message", "event_id": "$1", "sender": "@mod:matrix.example.com",
+                 "content": {"msgtype": "m.text", "body": "!approve 123"}},
+                {"type": "m.reaction", "event_id": "$2", "sender": "@mod:matrix.example.com",
+                 "content": {}}
+            ]}}}}
+        }"#;
+        let (next_batch, messages) = matrix_client.parse_sync("!room", response).unwrap();
+        assert_eq!(next_batch, "s72595_4483_1934");
+        assert_eq!(messages, vec![RoomMessage{
+            event_id: "$1".to_string(),
+            sender: "@mod:matrix.example.com".to_string(),
+            body: "!approve 123".to_string(),
+        }]);
+        assert_eq!(matrix_client.parse_gap("!room", response), None);
+    }
+
+    #[test]
+    fn parse_gap() {
+        let matrix_client = Matrix::new("matrix.example.com".to_string(), "token".to_string());
+        let response = r#"{
+            "next_batch": "s3",
+            "rooms": {"join": {"!room:matrix.example.com": {"timeline": {
+                "limited": true, "prev_batch": "t2", "events": []
+            }}}}
+        }"#;
+        assert_eq!(matrix_client.parse_gap("!room", response).as_deref(), Some("t2"));
+        let response = r#"{"start": "t2", "end": "t1", "chunk": [
+            {"type": "m.room.message", "event_id": "$2", "sender": "@mod:matrix.example.com",
+             "content": {"body": "!reject 2"}},
+            {"type": "m.room.message", "event_id": "$1", "sender": "@mod:matrix.example.com",
+             "content": {"body": "!approve 1"}}
+        ]}"#;
+        let (end, messages) = Matrix::parse_messages(response).unwrap();
+        assert_eq!(end.as_deref(), Some("t1"));
+        assert_eq!(messages.iter().map(|message| message.event_id.as_str()).collect::<Vec<_>>(), vec!["$1", "$2"]);
+        let (end, messages) = Matrix::parse_messages(r#"{"start": "t1", "chunk": []}"#).unwrap();
+        assert_eq!(end, None);
+        assert!(messages.is_empty());
+    }
+
     #[tokio::test]
     async fn post_message() {
         dotenv().ok();
@@ -0,0 +1,173 @@
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Url};
+use std::path::Path;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::debug;
+use super::mention::Attachment;
+use super::{Error, Matrix};
+
+// Where the attachments are copied, so they outlive the remote instance
+#[derive(Debug, Clone, PartialEq)]
+pub enum Archive{
+    Directory(String),
+    Matrix,
+}
+
+// Copies the attachments of the mentions within a size limit. Only the
+// content types that start with an allowed one, like `image/`, are copied.
+pub struct MediaArchive{
+    archive: Archive,
+    max_size: u64,
+    content_types: Vec<String>,
+    client: Client,
+}
+
+impl FromStr for Archive{
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value.split_once(':') {
+            None if value == "matrix" => Ok(Self::Matrix),
+            Some(("directory", directory)) if !directory.is_empty() => Ok(Self::Directory(directory.to_string())),
+            _ => Err(format!("Unknown archive {}", value).into()),
+        }
+    }
+}
+
+// Last segment of the url, with only safe characters
+fn get_filename(url: &str) -> String{
+    let filename: String = Url::parse(url).ok()
+        .and_then(|url| url.path_segments()?.next_back().map(|segment| segment.to_string()))
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        .collect();
+    if filename.trim_matches('.').is_empty(){
+        "attachment".to_string()
+    }else{
+        filename
+    }
+}
+
+impl MediaArchive{
+    pub fn new(archive: Archive, max_size: u64, content_types: Vec<String>) -> Self{
+        let client = Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        Self {
+            archive,
+            max_size,
+            content_types,
+            client,
+        }
+    }
+
+    fn accepts(&self, content_type: &str) -> bool{
+        self.content_types.iter().any(|allowed| content_type.starts_with(allowed.as_str()))
+    }
+
+    // Content type and content of an attachment, when it is allowed
+    async fn download(&self, url: &str) -> Result<(String, Vec<u8>), Error>{
+        debug!("download: {url}");
+        let mut response = self.client.get(url).send().await?.error_for_status()?;
+        let content_type = response.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_lowercase())
+            .unwrap_or_default();
+        if !self.accepts(&content_type){
+            return Err(format!("Content type {} of {} not allowed", content_type, url).into());
+        }
+        if response.content_length().is_some_and(|length| length > self.max_size){
+            return Err(format!("{} is bigger than {} bytes", url, self.max_size).into());
+        }
+        let mut content = Vec::new();
+        while let Some(chunk) = response.chunk().await?{
+            content.extend_from_slice(&chunk);
+            if content.len() as u64 > self.max_size{
+                return Err(format!("{} is bigger than {} bytes", url, self.max_size).into());
+            }
+        }
+        Ok((content_type, content))
+    }
+
+    // Copies an attachment of a status and returns where, as a path or as a
+    // `mxc://` uri
+    pub async fn archive(&self, matrix: &Matrix, status_id: &str, index: usize,
+            attachment: &Attachment) -> Result<String, Error>{
+        let (content_type, content) = self.download(&attachment.url).await?;
+        let filename = format!("{}-{}", index, get_filename(&attachment.url));
+        match &self.archive {
+            Archive::Directory(directory) => {
+                let directory = Path::new(directory).join(status_id);
+                fs::create_dir_all(&directory)?;
+                let path = directory.join(filename);
+                fs::write(&path, content)?;
+                Ok(path.to_string_lossy().to_string())
+            },
+            Archive::Matrix => matrix.upload(&content_type, &filename, content).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{get_filename, Archive, MediaArchive};
+    use crate::models::mention::Attachment;
+    use crate::models::Matrix;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn get_attachment(url: String) -> Attachment{
+        Attachment{
+            kind: "image".to_string(),
+            url,
+            preview_url: None,
+            description: Some("Un gato".to_string()),
+            archived: None,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("matrix".parse::<Archive>().unwrap(), Archive::Matrix);
+        assert_eq!("directory:media".parse::<Archive>().unwrap(), Archive::Directory("media".to_string()));
+        assert!("directory:".parse::<Archive>().is_err());
+        assert_eq!(get_filename("https://files.social/media/a%20b/cat.png?x=1"), "cat.png");
+        assert_eq!(get_filename("https://files.social/.."), "attachment");
+    }
+
+    #[tokio::test]
+    async fn archive_in_directory() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cat.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![1u8; 10], "image/png"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/big.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![1u8; 100], "image/png"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/script.js"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![1u8; 10], "text/javascript"))
+            .mount(&server)
+            .await;
+        let directory = std::env::temp_dir().join(format!("watchdog-media-{}", std::process::id()));
+        let archive = MediaArchive::new(Archive::Directory(directory.to_string_lossy().to_string()),
+            50, vec!["image/".to_string()]);
+        let matrix = Matrix::new("matrix.example".to_string(), "token".to_string());
+        let archived = archive.archive(&matrix, "456", 0, &get_attachment(format!("{}/cat.png", server.uri())))
+            .await.unwrap();
+        assert!(archived.ends_with("0-cat.png"));
+        assert_eq!(std::fs::read(&archived).unwrap().len(), 10);
+        assert!(archive.archive(&matrix, "456", 1, &get_attachment(format!("{}/big.png", server.uri()))).await.is_err());
+        assert!(archive.archive(&matrix, "456", 2, &get_attachment(format!("{}/script.js", server.uri()))).await.is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use html2md::parse_html;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use super::html::{escape, is_web_url, sanitize};
+
+/// Media attached to a status
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Attachment{
+    /// `image`, `gifv`, `video`, `audio` or `unknown`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url: String,
+    pub preview_url: Option<String>,
+    /// Alt text
+    pub description: Option<String>,
+    /// Local path or `mxc://` uri of the copy, when it was archived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mention{
+    pub id: String,
+    pub status_id: String,
+    pub in_reply_to_id: Option<String>,
+    pub in_reply_to_account_id: Option<String>,
+    pub url: Option<String>,
+    pub uri: Option<String>,
+    pub content: String,
+    pub created_at: String,
+    // Only for statuses edited by their author
+    pub edited_at: Option<String>,
+    pub language: Option<String>,
+    pub visibility: Option<String>,
+    pub account_id: String,
+    pub name: String,
+    pub nickname: String,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    // (url, acct) of every account mentioned in the status
+    pub mentions: Vec<(String, String)>,
+    // (url, name) of every hashtag in the status
+    pub tags: Vec<(String, String)>,
+    pub media: Vec<Attachment>,
+}
+
+fn get_str(value: &Value, key: &str) -> Option<String>{
+    value.get(key).and_then(|v| v.as_str()).map(|v| v.to_string())
+}
+
+fn get_pairs(status: &Value, key: &str, first: &str, second: &str) -> Vec<(String, String)>{
+    status.get(key)
+        .and_then(|v| v.as_array())
+        .map(|items| items.iter()
+            .filter_map(|item| Some((get_str(item, first)?, get_str(item, second)?)))
+            .collect())
+        .unwrap_or_default()
+}
+
+impl Mention{
+    pub fn from_notification(notification: &Value) -> Option<Mention>{
+        Self::build(get_str(notification, "id")?, notification.get("status")?, notification.get("account")?)
+    }
+
+    // Statuses found by search have no notification, so they are identified
+    // by their own id with a `status:` prefix
+    pub fn from_status(status: &Value) -> Option<Mention>{
+        Self::build(format!("status:{}", get_str(status, "id")?), status, status.get("account")?)
+    }
+
+    fn build(id: String, status: &Value, account: &Value) -> Option<Mention>{
+        let media = status.get("media_attachments")
+            .and_then(|v| v.as_array())
+            .map(|items| items.iter()
+                .filter_map(|item| Some(Attachment{
+                    kind: get_str(item, "type").unwrap_or("unknown".to_string()),
+                    url: get_str(item, "url")?,
+                    preview_url: get_str(item, "preview_url"),
+                    description: get_str(item, "description"),
+                    archived: None,
+                }))
+                .collect())
+            .unwrap_or_default();
+        Some(Mention{
+            id,
+            status_id: get_str(status, "id")?,
+            in_reply_to_id: get_str(status, "in_reply_to_id"),
+            in_reply_to_account_id: get_str(status, "in_reply_to_account_id"),
+            url: get_str(status, "url"),
+            uri: get_str(status, "uri"),
+            content: get_str(status, "content")?,
+            created_at: get_str(status, "created_at")?,
+            edited_at: get_str(status, "edited_at"),
+            language: get_str(status, "language"),
+            visibility: get_str(status, "visibility"),
+            account_id: get_str(account, "id").unwrap_or_default(),
+            name: get_str(account, "username")?,
+            nickname: get_str(account, "acct")?,
+            display_name: get_str(account, "display_name").filter(|name| !name.is_empty()),
+            avatar: get_str(account, "avatar"),
+            mentions: get_pairs(status, "mentions", "url", "acct"),
+            tags: get_pairs(status, "tags", "url", "name"),
+            media,
+        })
+    }
+
+    // Mentions and hashtags point to the remote instance; point them to our
+    // own instance so moderators can interact with them directly.
+    pub fn get_links(&self, base_uri: &str) -> HashMap<String, String>{
+        let base_uri = base_uri.trim_end_matches('/');
+        let mentions = self.mentions.iter()
+            .map(|(url, acct)| (url.to_string(), format!("{}/@{}", base_uri, acct)));
+        let tags = self.tags.iter()
+            .map(|(url, name)| (url.to_string(), format!("{}/tags/{}", base_uri, name)));
+        mentions.chain(tags).collect()
+    }
+
+    pub fn get_text(&self) -> String{
+        let mut text = format!("Src: Mastodon. From: @{}. Content: {}", self.nickname, parse_html(&self.content));
+        if let Some(url) = &self.url{
+            text.push_str(&format!(" Url: {}", url));
+        }
+        for attachment in self.media.iter(){
+            text.push_str(&format!(" Media: {}", attachment.url));
+            if let Some(description) = &attachment.description{
+                text.push_str(&format!(" ({})", description));
+            }
+        }
+        text
+    }
+
+    pub fn get_html(&self, base_uri: &str) -> String{
+        let url = match &self.url{
+            Some(url) if is_web_url(url) => format!("<li>Url: <a href=\"{0}\">{0}</a></li>", escape(url)),
+            _ => "".to_string(),
+        };
+        let media: Vec<String> = self.media.iter()
+            .filter(|attachment| is_web_url(&attachment.url))
+            .map(|attachment| {
+                let description = escape(attachment.description.as_deref().unwrap_or(&attachment.url));
+                // Images copied to the media repository are shown inline
+                let image = match &attachment.archived{
+                    Some(uri) if attachment.kind == "image" && uri.starts_with("mxc://") => {
+                        format!("<br><img src=\"{}\" alt=\"{}\">", escape(uri), description)
+                    },
+                    _ => "".to_string(),
+                };
+                format!("<li><a href=\"{}\">{}</a>{}</li>", escape(&attachment.url), description, image)
+            })
+            .collect();
+        let media = if media.is_empty(){
+            "".to_string()
+        }else{
+            format!("<p>Media:</p><ul>{}</ul>", media.join(""))
+        };
+        format!(
+            "<h6>Src: Mastodon</h6><ul><li>Id: {}</li><li>From: @{}</li>{}<li>Content:</li></ul>{}{}",
+            escape(&self.id),
+            escape(&self.nickname),
+            url,
+            sanitize(&self.content, self.get_links(base_uri)),
+            media
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::Mention;
+    use crate::models::fixtures::get_notification;
+    use serde_json::json;
+
+    #[test]
+    fn get_html() {
+        let mut notification = get_notification("123", json!({
+            "id": "456",
+            "in_reply_to_id": null,
+            "url": "https://example.com/@evil/456",
+            "content": "<p>Hola <script>alert(1)</script><a href=\"https://example.com/tags/idea\" class=\"mention hashtag\">#<span>idea</span></a></p>",
+            "mentions": [],
+            "tags": [{"name": "idea", "url": "https://example.com/tags/idea"}],
+            "media_attachments": [{"url": "https://example.com/a.png", "description": "A <cat>"}]
+        }));
+        notification["account"] = json!({"username": "evil", "acct": "<b>evil</b>@example.com"});
+        let mention = Mention::from_notification(&notification).unwrap();
+        assert_eq!(mention.get_html("https://mastodon.example"), concat!(
+            "<h6>Src: Mastodon</h6><ul><li>Id: 123</li><li>From: @&lt;b&gt;evil&lt;/b&gt;@example.com</li>",
+            "<li>Url: <a href=\"https://example.com/@evil/456\">https://example.com/@evil/456</a></li>",
+            "<li>Content:</li></ul>",
+            "<p>Hola <a href=\"https://mastodon.example/tags/idea\">#<span>idea</span></a></p>",
+            "<p>Media:</p><ul><li><a href=\"https://example.com/a.png\">A &lt;cat&gt;</a></li></ul>"
+        ));
+    }
+
+    #[test]
+    fn from_status() {
+        let status = json!({
+            "id": "456",
+            "in_reply_to_id": null,
+            "url": "https://example.com/@a/456",
+            "created_at": "2023-07-25T10:00:00.000Z",
+            "content": "<p>#idea Rust</p>",
+            "account": {"id": "7", "username": "a", "acct": "a@example.com"}
+        });
+        let mention = Mention::from_status(&status).unwrap();
+        assert_eq!(mention.id, "status:456");
+        assert_eq!(mention.status_id, "456");
+        assert_eq!(mention.nickname, "a@example.com");
+    }
+}
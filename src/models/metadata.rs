@@ -0,0 +1,166 @@
+use html2md::parse_html;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use super::html::to_plain_text;
+use super::mention::Attachment;
+use super::Mention;
+
+// Version of the feedback payload that carries the metadata. Version 1 is the
+// original payload, without it.
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Account{
+    pub id: String,
+    pub username: String,
+    pub acct: String,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+}
+
+// Status the mention is replying to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Parent{
+    pub id: String,
+    pub url: Option<String>,
+    pub account: Option<String>,
+    pub content_html: String,
+    pub content_text: String,
+}
+
+// Statuses above the mention in its thread. The root is only kept when it is
+// not the parent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context{
+    pub parent: Option<Parent>,
+    pub root: Option<Parent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metadata{
+    pub status_id: String,
+    pub status_url: Option<String>,
+    pub status_uri: Option<String>,
+    pub created_at: String,
+    pub language: Option<String>,
+    pub visibility: Option<String>,
+    pub account: Account,
+    pub attachments: Vec<Attachment>,
+    pub hashtags: Vec<String>,
+    pub in_reply_to_id: Option<String>,
+    pub in_reply_to_account_id: Option<String>,
+    pub parent: Option<Parent>,
+    // Status that started the thread, like the announcement of an episode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<Parent>,
+    pub content_html: String,
+    pub content_markdown: String,
+    pub content_text: String,
+}
+
+impl Parent{
+    pub fn from_status(status: &Value) -> Option<Parent>{
+        let content_html = status.get("content")?.as_str()?.to_string();
+        Some(Parent{
+            id: status.get("id")?.as_str()?.to_string(),
+            url: status.get("url").and_then(|v| v.as_str()).map(|v| v.to_string()),
+            account: status.get("account")
+                .and_then(|account| account.get("acct"))
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string()),
+            content_text: to_plain_text(&content_html),
+            content_html,
+        })
+    }
+}
+
+impl Context{
+    // From the response of `/api/v1/statuses/:id/context`, whose ancestors
+    // come oldest first
+    pub fn from_context(context: &Value, in_reply_to_id: &str) -> Self{
+        let ancestors: Vec<Parent> = context.get("ancestors")
+            .and_then(|v| v.as_array())
+            .map(|statuses| statuses.iter().filter_map(Parent::from_status).collect())
+            .unwrap_or_default();
+        let parent = ancestors.iter().find(|status| status.id == in_reply_to_id).cloned();
+        let root = ancestors.into_iter().next()
+            .filter(|root| Some(&root.id) != parent.as_ref().map(|parent| &parent.id));
+        Self { parent, root }
+    }
+
+    // Text of the status that started the thread
+    pub fn get_root_text(&self) -> Option<&str>{
+        self.root.as_ref().or(self.parent.as_ref())
+            .map(|status| status.content_text.as_str())
+    }
+}
+
+impl Metadata{
+    pub fn new(mention: &Mention, context: Context) -> Self{
+        Self {
+            status_id: mention.status_id.to_string(),
+            status_url: mention.url.clone(),
+            status_uri: mention.uri.clone(),
+            created_at: mention.created_at.to_string(),
+            language: mention.language.clone(),
+            visibility: mention.visibility.clone(),
+            account: Account{
+                id: mention.account_id.to_string(),
+                username: mention.name.to_string(),
+                acct: mention.nickname.to_string(),
+                display_name: mention.display_name.clone(),
+                avatar: mention.avatar.clone(),
+            },
+            attachments: mention.media.clone(),
+            hashtags: mention.tags.iter().map(|(_, name)| name.to_string()).collect(),
+            in_reply_to_id: mention.in_reply_to_id.clone(),
+            in_reply_to_account_id: mention.in_reply_to_account_id.clone(),
+            parent: context.parent,
+            root: context.root,
+            content_html: mention.content.to_string(),
+            content_markdown: parse_html(&mention.content),
+            content_text: to_plain_text(&mention.content),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{Context, Metadata};
+    use crate::models::fixtures::get_mention;
+    use serde_json::json;
+
+    #[test]
+    fn from_mention() {
+        let mention = get_mention("123", json!({
+            "id": "456",
+            "in_reply_to_id": "455",
+            "url": "https://mastodon.social/@atareao/456",
+            "uri": "https://mastodon.social/users/atareao/statuses/456",
+            "language": "es",
+            "visibility": "public",
+            "content": "<p>Una <a href=\"https://mastodon.social/tags/idea\">#<span>idea</span></a></p>",
+            "tags": [{"name": "idea", "url": "https://mastodon.social/tags/idea"}],
+            "media_attachments": [{"type": "image", "url": "https://files.social/a.png",
+                "preview_url": "https://files.social/a_small.png", "description": null}]
+        }));
+        let context = Context::from_context(&json!({
+            "ancestors": [
+                {"id": "450", "account": {"acct": "podcast"}, "content": "<p>Episodio 512: Rust</p>"},
+                {"id": "455", "account": {"acct": "podcast"}, "content": "<p>Nuevo episodio<br>¿Qué os parece?</p>"}
+            ],
+            "descendants": []
+        }), "455");
+        assert_eq!(context.get_root_text(), Some("Episodio 512: Rust"));
+        let metadata = Metadata::new(&mention, context);
+        assert_eq!(metadata.account.display_name, None);
+        assert_eq!(metadata.hashtags, vec!["idea"]);
+        assert_eq!(metadata.content_text, "Una #idea");
+        assert_eq!(metadata.parent.as_ref().unwrap().content_text, "Nuevo episodio\n¿Qué os parece?");
+        let value = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(value["attachments"][0]["type"], "image");
+        assert_eq!(value["account"]["id"], "9");
+        assert_eq!(value["parent"]["account"], "podcast");
+        assert_eq!(value["root"]["id"], "450");
+    }
+}
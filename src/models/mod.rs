@@ -1,15 +1,52 @@
+mod command;
 mod config;
+mod digest;
+mod event;
+mod export;
 mod feedback;
+mod html;
+mod lifecycle;
 mod mastodon;
 mod matrix;
+mod media;
+mod mention;
+mod profile;
+mod metadata;
 mod message;
+pub mod oauth;
+mod stats;
+mod store;
+mod sink;
+mod software;
+mod threads;
+mod webhook;
 mod zinc;
+mod zinc_buffer;
+mod zinc_layer;
 
+pub use command::Command;
 pub use config::Config;
-pub use feedback::Feedback;
+pub use digest::{Digest, next_run, parse_schedule, MAX_TOOT_LENGTH};
+pub use event::{Delivery, Event};
+pub use export::{export, Format};
+pub use feedback::{Feedback, FeedbackSink};
+pub use lifecycle::{Notified, Templates, get_state};
+pub use webhook::{Webhook, get_fields, read_webhooks};
 pub use zinc::Zinc;
+pub use zinc_buffer::ZincBuffer;
+pub use zinc_layer::ZincLayer;
 pub use mastodon::Mastodon;
 pub use matrix::Matrix;
+pub use media::{Archive, MediaArchive};
+pub use mention::Mention;
+pub use oauth::{Credential, Credentials};
+pub use profile::{Acknowledge, Action, Profile, Timeline, read_profiles, DEFAULT_PROFILE};
+pub use metadata::{Context, Metadata};
+pub use sink::HttpSink;
+pub use software::Software;
+pub use stats::Stats;
+pub use store::{Filter, Record, Store, ANSWERED, APPLIED, REJECTED, RETRACTED};
+pub use threads::{Thread, Threads};
 pub use message::{
     check_key,
     check_comment,
@@ -17,4 +54,44 @@ pub use message::{
 
 pub type Error = Box<dyn std::error::Error>;
 
+// Mastodon ids grow over time, but they are not always numbers, so the
+// shortest and then lexicographically smallest id is the oldest one
+pub fn compare_ids(a: &str, b: &str) -> std::cmp::Ordering{
+    a.len().cmp(&b.len()).then(a.cmp(b))
+}
 
+// Ids are only unique within an instance, so notifications and statuses are
+// known outside of the store as `<profile>/<id>`
+pub fn qualify(profile: &str, id: &str) -> String{
+    format!("{}/{}", profile, id)
+}
+
+// Id of a qualified key, or the key itself when it is a bare id
+pub fn unqualify(key: &str) -> &str{
+    key.rsplit_once('/').map(|(_, id)| id).unwrap_or(key)
+}
+
+// Notifications and mentions shared by the tests of the models
+#[cfg(test)]
+pub mod fixtures{
+    use serde_json::{json, Value};
+    use super::Mention;
+
+    // Notification of a mention by @atareao@mastodon.social. The status only
+    // needs the fields that matter to the test.
+    pub fn get_notification(id: &str, status: Value) -> Value{
+        let mut notification = json!({
+            "id": id,
+            "account": {"id": "9", "username": "atareao", "acct": "atareao@mastodon.social", "display_name": ""},
+            "status": {"id": format!("9{}", id), "created_at": "2023-07-25T10:00:00.000Z", "content": ""}
+        });
+        for (key, value) in status.as_object().cloned().unwrap_or_default(){
+            notification["status"][key] = value;
+        }
+        notification
+    }
+
+    pub fn get_mention(id: &str, status: Value) -> Mention{
+        Mention::from_notification(&get_notification(id, status)).unwrap()
+    }
+}
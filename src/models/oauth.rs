@@ -0,0 +1,176 @@
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::fs;
+use tracing::{debug, info};
+use super::Error;
+
+pub const CLIENT_NAME: &str = "mastodon-watchdog";
+// The code is shown to the user instead of being sent to a redirect
+pub const REDIRECT_URI: &str = "urn:ietf:wg:oauth:2.0:oob";
+// Reading the account is only needed to verify the token, and searching,
+// statuses and lists only for the profiles with searches and timelines.
+// Statuses also cover the notifications marker and boosts.
+pub const SCOPES: &str = "read:notifications read:accounts read:search read:statuses read:lists \
+    write:notifications write:statuses write:favourites write:bookmarks";
+
+// Application registered in a Mastodon instance
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct App{
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Token{
+    access_token: String,
+}
+
+// Token of a profile, with the application that obtained it
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Credential{
+    pub base_uri: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub access_token: String,
+    // Account authorized, as user@instance
+    pub account: String,
+}
+
+// Tokens obtained with `auth mastodon`, by profile
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Credentials{
+    #[serde(default)]
+    profiles: BTreeMap<String, Credential>,
+}
+
+pub async fn register_app(base_uri: &str) -> Result<App, Error>{
+    let url = format!("{}/api/v1/apps", base_uri.trim_end_matches('/'));
+    debug!("{}", &url);
+    let params = [
+        ("client_name", CLIENT_NAME),
+        ("redirect_uris", REDIRECT_URI),
+        ("scopes", SCOPES),
+    ];
+    Ok(Client::new()
+        .post(url)
+        .form(&params)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+pub fn get_authorize_url(base_uri: &str, app: &App) -> Result<String, Error>{
+    let url = Url::parse_with_params(&format!("{}/oauth/authorize", base_uri.trim_end_matches('/')), [
+        ("client_id", app.client_id.as_str()),
+        ("scope", SCOPES),
+        ("redirect_uri", REDIRECT_URI),
+        ("response_type", "code"),
+    ])?;
+    Ok(url.to_string())
+}
+
+pub async fn get_token(base_uri: &str, app: &App, code: &str) -> Result<String, Error>{
+    let url = format!("{}/oauth/token", base_uri.trim_end_matches('/'));
+    debug!("{}", &url);
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("client_id", &app.client_id),
+        ("client_secret", &app.client_secret),
+        ("redirect_uri", REDIRECT_URI),
+        ("scope", SCOPES),
+    ];
+    let token: Token = Client::new()
+        .post(url)
+        .form(&params)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(token.access_token)
+}
+
+impl Credentials{
+    pub fn read(filename: &str) -> Result<Credentials, Error>{
+        info!("read");
+        if !Path::new(filename).exists(){
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(filename)?;
+        Ok(toml::from_str(&data)?)
+    }
+
+    // Only the owner can read the file, as it holds the tokens
+    pub fn save(&self, filename: &str) -> Result<(), std::io::Error>{
+        info!("save");
+        let toml = toml::to_string(&self).unwrap();
+        fs::write(filename, toml)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(filename, fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, profile: &str) -> Option<&Credential>{
+        self.profiles.get(profile)
+    }
+
+    pub fn insert(&mut self, profile: &str, credential: Credential){
+        self.profiles.insert(profile.to_string(), credential);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{get_authorize_url, get_token, register_app, App, Credential, Credentials};
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn authorization_code_flow() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/apps"))
+            .and(body_string_contains("scopes=read%3Anotifications+read%3Aaccounts+read%3Asearch+read%3Astatuses+read%3Alists+write%3Anotifications+write%3Astatuses+write%3Afavourites+write%3Abookmarks"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"id": "1", "name": "mastodon-watchdog", "client_id": "id", "client_secret": "secret"}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/oauth/token"))
+            .and(body_string_contains("code=abc"))
+            .and(body_string_contains("client_secret=secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"access_token": "token", "token_type": "Bearer", "scope": "read:notifications"}"#))
+            .mount(&server)
+            .await;
+        let app = register_app(&server.uri()).await.unwrap();
+        assert_eq!(app, App{client_id: "id".to_string(), client_secret: "secret".to_string()});
+        let url = get_authorize_url(&server.uri(), &app).unwrap();
+        assert!(url.starts_with(&format!("{}/oauth/authorize?client_id=id&", server.uri())));
+        assert!(url.contains("response_type=code"));
+        assert_eq!(get_token(&server.uri(), &app, "abc").await.unwrap(), "token");
+    }
+
+    #[test]
+    fn credentials() {
+        let mut credentials = Credentials::default();
+        credentials.insert("default", Credential{
+            base_uri: "https://mastodon.social".to_string(),
+            client_id: "id".to_string(),
+            client_secret: "secret".to_string(),
+            access_token: "token".to_string(),
+            account: "atareao@mastodon.social".to_string(),
+        });
+        let credentials: Credentials = toml::from_str(&toml::to_string(&credentials).unwrap()).unwrap();
+        assert_eq!(credentials.get("default").unwrap().access_token, "token");
+        assert!(credentials.get("podcast").is_none());
+    }
+}
@@ -0,0 +1,352 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::fs;
+use tracing::info;
+use super::{check_comment, check_key, Error, Software};
+
+// Name of the profile built from the environment when there is no profiles file
+pub const DEFAULT_PROFILE: &str = "default";
+
+// Mastodon account watched by the watchdog, configured in the profiles file
+//
+// [[profile]]
+// name = "atareao"
+// base_uri = "https://mastodon.social"
+// token = "..."   # or authorize the profile with `auth mastodon`
+// software = "gotosocial"   # detected when missing
+// sinks = ["feedback", "matrix", "webhook:slack"]
+// acknowledge = "marker"
+// episode_pattern = "(?i)episodio\\s+(\\d+)"
+// searches = ["#atareao", "atareao"]
+// [[profile.timelines]]
+// tag = "atareao"     # or list = "42"
+// categorized = true  # only statuses with the hashtag of a rule
+// [[profile.rules]]
+// category = "idea"
+// hashtag = "idea"
+// [profile.templates]
+// idea = "Gracias por tu idea @{{nickname}}"
+// [profile.actions]
+// idea = "favourite"
+//
+// Rules are checked in order and mentions that match none of them are
+// classified as `mencion`. A reply without reference takes the episode from
+// the status that started its thread, with the first group of the pattern.
+// Categories are thanked with a reply, when they have a template, unless
+// they have another action.
+#[derive(Debug, Deserialize)]
+pub struct Profile{
+    pub name: String,
+    pub base_uri: String,
+    // Empty to use the token stored by `auth mastodon`
+    #[serde(default)]
+    pub token: String,
+    // `mastodon`, `pleroma`, `akkoma`, `gotosocial` or `misskey`
+    #[serde(default)]
+    pub software: Option<Software>,
+    #[serde(default = "default_rules")]
+    pub rules: Vec<Rule>,
+    #[serde(default = "default_templates")]
+    pub templates: HashMap<String, String>,
+    #[serde(default)]
+    pub actions: HashMap<String, Action>,
+    // `feedback`, `mastodon`, `matrix` or `webhook:<name>`. Empty means every sink
+    #[serde(default)]
+    pub sinks: Vec<String>,
+    #[serde(default = "default_episode_pattern")]
+    pub episode_pattern: String,
+    // What is done with a notification once every sink has its mention
+    #[serde(default)]
+    pub acknowledge: Acknowledge,
+    // Queries whose statuses are processed like mentions
+    #[serde(default)]
+    pub searches: Vec<String>,
+    // Timelines whose statuses are processed like mentions
+    #[serde(default)]
+    pub timelines: Vec<Timeline>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule{
+    pub category: String,
+    pub hashtag: String,
+    // The message is the episode number written after the hashtag
+    #[serde(default)]
+    pub reference: bool,
+}
+
+// `dismiss` removes the notification. With `marker` the notifications read
+// marker is moved forward and used as the cursor, so that reading them in a
+// Mastodon client also skips them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Acknowledge{
+    #[default]
+    None,
+    Dismiss,
+    Marker,
+}
+
+// How a mention is thanked on Mastodon
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action{
+    #[default]
+    Reply,
+    Favourite,
+    Bookmark,
+    Boost,
+    None,
+}
+
+// Hashtag or list timeline, exactly one of both
+#[derive(Debug, Clone, Deserialize)]
+pub struct Timeline{
+    pub tag: Option<String>,
+    pub list: Option<String>,
+    #[serde(default)]
+    pub categorized: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProfilesFile{
+    #[serde(default)]
+    profile: Vec<Profile>,
+}
+
+fn default_rules() -> Vec<Rule>{
+    vec![
+        Rule{category: "idea".to_string(), hashtag: "idea".to_string(), reference: false},
+        Rule{category: "pregunta".to_string(), hashtag: "pregunta".to_string(), reference: false},
+        Rule{category: "comentario".to_string(), hashtag: "comentario".to_string(), reference: true},
+    ]
+}
+
+fn default_episode_pattern() -> String{
+    r"(?i)episodio\s+(\d+)".to_string()
+}
+
+fn default_templates() -> HashMap<String, String>{
+    HashMap::from([
+        ("idea".to_string(), "Gracias por tu idea @{{nickname}}".to_string()),
+        ("pregunta".to_string(), "Gracias por tu pregunta @{{nickname}}".to_string()),
+        ("comentario".to_string(), "Gracias por tu comentario @{{nickname}}".to_string()),
+    ])
+}
+
+pub fn read_profiles(filename: &str) -> Result<Vec<Profile>, Error>{
+    info!("read");
+    if !Path::new(filename).exists(){
+        return Ok(Vec::new());
+    }
+    let data = fs::read_to_string(filename)?;
+    let file: ProfilesFile = toml::from_str(&data)?;
+    let mut names: Vec<&str> = file.profile.iter().map(|profile| profile.name.as_str()).collect();
+    names.sort();
+    names.dedup();
+    if names.len() != file.profile.len(){
+        return Err("Profile names must be unique".into());
+    }
+    for profile in file.profile.iter(){
+        if let Err(error) = Regex::new(&profile.episode_pattern){
+            return Err(format!("Wrong episode pattern of profile {}: {}", profile.name, error).into());
+        }
+        if profile.timelines.iter().any(|timeline| timeline.tag.is_some() == timeline.list.is_some()){
+            return Err(format!("Timelines of profile {} need either a tag or a list", profile.name).into());
+        }
+    }
+    Ok(file.profile)
+}
+
+impl FromStr for Acknowledge{
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value {
+            "none" => Ok(Self::None),
+            "dismiss" => Ok(Self::Dismiss),
+            "marker" => Ok(Self::Marker),
+            _ => Err(format!("Unknown acknowledge {}", value).into()),
+        }
+    }
+}
+
+impl FromStr for Action{
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value {
+            "reply" => Ok(Self::Reply),
+            "favourite" => Ok(Self::Favourite),
+            "bookmark" => Ok(Self::Bookmark),
+            "boost" => Ok(Self::Boost),
+            "none" => Ok(Self::None),
+            _ => Err(format!("Unknown action {}", value).into()),
+        }
+    }
+}
+
+impl Timeline{
+    // Path of the timeline in the Mastodon API
+    pub fn get_path(&self) -> String{
+        match (&self.tag, &self.list) {
+            (Some(tag), _) => format!("tag/{}", tag.trim_start_matches('#')),
+            (None, Some(list)) => format!("list/{}", list),
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+impl Profile{
+    pub fn new(name: &str, base_uri: &str, token: &str) -> Self{
+        Self {
+            name: name.to_string(),
+            base_uri: base_uri.to_string(),
+            token: token.to_string(),
+            software: None,
+            rules: default_rules(),
+            templates: default_templates(),
+            actions: HashMap::new(),
+            sinks: Vec::new(),
+            episode_pattern: default_episode_pattern(),
+            acknowledge: Acknowledge::None,
+            searches: Vec::new(),
+            timelines: Vec::new(),
+        }
+    }
+
+    // Category and message of a mention, or None when it must be ignored
+    pub fn classify(&self, content: &str) -> Option<(String, String)>{
+        self.classify_in_thread(content, None)
+    }
+
+    // Like `classify`, with the text of the status that started the thread
+    pub fn classify_in_thread(&self, content: &str, root: Option<&str>) -> Option<(String, String)>{
+        for rule in self.rules.iter(){
+            if rule.reference{
+                if let Some((reference, _)) = check_comment(&rule.hashtag, content){
+                    let episode = root.and_then(|root| self.get_episode(root));
+                    return reference.clone().filter(|reference| !reference.is_empty())
+                        .or(episode)
+                        .or(reference)
+                        .map(|reference| (rule.category.to_string(), reference));
+                }
+            }else if let Some(message) = check_key(&rule.hashtag, content){
+                return Some((rule.category.to_string(), message));
+            }
+        }
+        Some(("mencion".to_string(), content.to_string()))
+    }
+
+    // Whether the content has the hashtag of a rule
+    // Categories of the rules, and `mencion` for the rest of the mentions
+    pub fn has_category(&self, category: &str) -> bool{
+        category == "mencion" || self.rules.iter().any(|rule| rule.category == category)
+    }
+
+    pub fn is_categorized(&self, content: &str) -> bool{
+        self.classify(content).is_some_and(|(category, _)| category != "mencion")
+    }
+
+    fn get_episode(&self, text: &str) -> Option<String>{
+        Regex::new(&self.episode_pattern).ok()?
+            .captures(text)?
+            .get(1)
+            .map(|episode| episode.as_str().to_string())
+    }
+
+    pub fn get_thanks(&self, category: &str, nickname: &str) -> Option<String>{
+        self.templates.get(category)
+            .map(|template| template.replace("{{nickname}}", nickname))
+    }
+
+    pub fn get_action(&self, category: &str) -> Action{
+        self.actions.get(category).copied().unwrap_or_default()
+    }
+
+    pub fn routes(&self, sink: &str) -> bool{
+        self.sinks.is_empty() || self.sinks.iter().any(|name| name == sink)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{Acknowledge, Action, Profile, ProfilesFile};
+    use crate::models::Software;
+
+    const PROFILES: &str = r#"
+        [[profile]]
+        name = "atareao"
+        base_uri = "https://mastodon.social"
+        token = "token"
+
+        [[profile]]
+        name = "podcast"
+        base_uri = "https://fosstodon.org"
+        token = "token"
+        sinks = ["matrix"]
+        software = "gotosocial"
+        acknowledge = "dismiss"
+        [[profile.rules]]
+        category = "sugerencia"
+        hashtag = "sugerencia"
+        [profile.templates]
+        sugerencia = "¡Anotado, @{{nickname}}!"
+        [profile.actions]
+        sugerencia = "favourite"
+        mencion = "none"
+        [[profile.timelines]]
+        tag = "podcast"
+        categorized = true
+        [[profile.timelines]]
+        list = "42"
+    "#;
+
+    #[test]
+    fn classify() {
+        let file: ProfilesFile = toml::from_str(PROFILES).unwrap();
+        let atareao = &file.profile[0];
+        assert_eq!(atareao.classify("<p>#idea Rust</p>"),
+            Some(("idea".to_string(), "<p>#idea Rust</p>".to_string())));
+        assert_eq!(atareao.classify("#comentario 512 genial"),
+            Some(("comentario".to_string(), "512".to_string())));
+        assert_eq!(atareao.classify("#comentario"), None);
+        assert_eq!(atareao.classify_in_thread("#comentario", Some("Episodio 512: Rust")),
+            Some(("comentario".to_string(), "512".to_string())));
+        assert_eq!(atareao.classify_in_thread("#comentario 511", Some("Episodio 512: Rust")),
+            Some(("comentario".to_string(), "511".to_string())));
+        assert_eq!(atareao.classify_in_thread("#comentario", Some("Hola")), None);
+        assert_eq!(atareao.get_thanks("pregunta", "a").as_deref(), Some("Gracias por tu pregunta @a"));
+        assert!(atareao.routes("webhook:slack"));
+        let podcast = &file.profile[1];
+        assert_eq!(podcast.classify("#idea y #sugerencia").unwrap().0, "sugerencia");
+        assert_eq!(podcast.classify("#idea").unwrap().0, "mencion");
+        assert_eq!(podcast.get_thanks("sugerencia", "a").as_deref(), Some("¡Anotado, @a!"));
+        assert_eq!(podcast.get_thanks("mencion", "a"), None);
+        assert_eq!(atareao.acknowledge, Acknowledge::None);
+        assert_eq!(podcast.acknowledge, Acknowledge::Dismiss);
+        assert_eq!(atareao.software, None);
+        assert_eq!(podcast.software, Some(Software::GoToSocial));
+        assert_eq!(atareao.get_action("idea"), Action::Reply);
+        assert_eq!(podcast.get_action("sugerencia"), Action::Favourite);
+        assert_eq!(podcast.get_action("mencion"), Action::None);
+        assert!(podcast.routes("matrix"));
+        assert!(!podcast.routes("feedback"));
+        assert!(podcast.is_categorized("#sugerencia"));
+        assert!(!podcast.is_categorized("#idea"));
+        assert!(podcast.has_category("sugerencia"));
+        assert!(podcast.has_category("mencion"));
+        assert!(!podcast.has_category("idea"));
+        let paths: Vec<String> = podcast.timelines.iter().map(|timeline| timeline.get_path()).collect();
+        assert_eq!(paths, ["tag/podcast", "list/42"]);
+    }
+
+    #[test]
+    fn default_profile() {
+        let profile = Profile::new("default", "https://mastodon.social", "token");
+        assert_eq!(profile.rules.len(), 3);
+    }
+}
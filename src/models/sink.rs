@@ -0,0 +1,121 @@
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Method, StatusCode};
+use std::fmt;
+use std::time::Duration;
+use tracing::{debug, error};
+use super::Error;
+
+#[derive(Debug)]
+pub struct HttpError{
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl fmt::Display for HttpError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "HTTP {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for HttpError{}
+
+// Delivers requests to an HTTP endpoint, retrying on network errors and
+// server errors. Client errors (4xx) are returned right away with the body.
+#[derive(Debug, Clone)]
+pub struct HttpSink{
+    client: Client,
+    retries: u32,
+}
+
+impl HttpSink{
+    pub fn new(retries: u32, connect_timeout: Duration, timeout: Duration) -> Self{
+        let client = Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(timeout)
+            .build()
+            .unwrap();
+        Self { client, retries }
+    }
+
+    pub async fn send(&self, method: Method, url: &str, headers: HeaderMap, body: Vec<u8>) -> Result<String, Error>{
+        let mut attempt = 0;
+        loop {
+            if attempt > 0{
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt - 1))).await;
+            }
+            attempt += 1;
+            debug!("{} {} (attempt {})", method, url, attempt);
+            let response = self.client
+                .request(method.clone(), url)
+                .headers(headers.clone())
+                .body(body.clone())
+                .send()
+                .await;
+            let error: Error = match response {
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await?;
+                    if status.is_success(){
+                        return Ok(body);
+                    }
+                    let error = HttpError{status, body};
+                    if !status.is_server_error(){
+                        return Err(error.into());
+                    }
+                    error.into()
+                },
+                Err(e) => e.into(),
+            };
+            if attempt > self.retries{
+                return Err(error);
+            }
+            error!("{} {} failed, retrying: {}", method, url, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{HttpError, HttpSink};
+    use reqwest::header::HeaderMap;
+    use reqwest::{Method, StatusCode};
+    use std::time::Duration;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn get_sink(retries: u32) -> HttpSink{
+        HttpSink::new(retries, Duration::from_secs(1), Duration::from_secs(5))
+    }
+
+    #[tokio::test]
+    async fn retry_on_server_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(201).set_body_string("{\"id\": 7}"))
+            .mount(&server)
+            .await;
+        let response = get_sink(1).send(Method::POST, &server.uri(), HeaderMap::new(), b"{}".to_vec()).await;
+        assert_eq!(response.unwrap(), "{\"id\": 7}");
+    }
+
+    #[tokio::test]
+    async fn fail_on_client_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(422).set_body_string("invalid category"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let error = get_sink(3).send(Method::POST, &server.uri(), HeaderMap::new(), b"{}".to_vec())
+            .await
+            .unwrap_err();
+        let error = error.downcast_ref::<HttpError>().unwrap();
+        assert_eq!(error.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.body, "invalid category");
+    }
+}
@@ -0,0 +1,161 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::str::FromStr;
+use super::{compare_ids, Error};
+
+// Software of the instance behind the Mastodon API. Every other one only
+// implements part of it, or implements it with its own quirks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Software{
+    #[default]
+    Mastodon,
+    Pleroma,
+    Akkoma,
+    GoToSocial,
+    // Forks of Misskey with a Mastodon API, like Firefish or Sharkey
+    Misskey,
+}
+
+impl FromStr for Software{
+    type Err = Error;
+
+    // Names used by nodeinfo, including the known forks
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value.to_lowercase().as_str() {
+            "mastodon" | "hometown" | "glitchsoc" => Ok(Self::Mastodon),
+            "pleroma" => Ok(Self::Pleroma),
+            "akkoma" => Ok(Self::Akkoma),
+            "gotosocial" => Ok(Self::GoToSocial),
+            "misskey" | "firefish" | "calckey" | "foundkey" | "sharkey" | "iceshrimp" => Ok(Self::Misskey),
+            _ => Err(format!("Unknown software {}", value).into()),
+        }
+    }
+}
+
+fn get_id(value: &Value) -> &str{
+    value.get("id").and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+// Newest id of a page of notifications or statuses, as sent by the instance
+pub fn get_max_id(values: &Value) -> Option<String>{
+    values.as_array()?.iter()
+        .map(|value| {
+            let mut value = value.clone();
+            stringify_ids(&mut value, &["id"]);
+            get_id(&value).to_string()
+        })
+        .filter(|id| !id.is_empty())
+        .max_by(|a, b| compare_ids(a, b))
+}
+
+// Some instances send ids as numbers
+fn stringify_ids(value: &mut Value, keys: &[&str]){
+    for key in keys{
+        if let Some(id) = value.get_mut(*key).filter(|id| id.is_number()){
+            *id = Value::String(id.to_string());
+        }
+    }
+}
+
+fn normalize_status(status: &mut Value){
+    stringify_ids(status, &["id", "in_reply_to_id", "in_reply_to_account_id"]);
+    if let Some(account) = status.get_mut("account"){
+        stringify_ids(account, &["id"]);
+    }
+}
+
+impl Software{
+    // `/api/v1/instance` only tells the version, like
+    // "2.7.2 (compatible; Pleroma 2.5.0)"
+    pub fn from_version(version: &str) -> Self{
+        let version = version.to_lowercase();
+        ["akkoma", "pleroma", "gotosocial", "misskey", "firefish", "calckey", "sharkey", "iceshrimp"].iter()
+            .find(|name| version.contains(*name))
+            .and_then(|name| name.parse().ok())
+            .unwrap_or_default()
+    }
+
+    // Only Mastodon is trusted to filter notifications by `types[]`
+    pub fn filters_types(&self) -> bool{
+        *self == Self::Mastodon
+    }
+
+    // Notifications as Mastodon sends them: only mentions and updates, with
+    // string ids, the account of the status and the newest first
+    pub fn normalize_notifications(&self, notifications: Value) -> Value{
+        if *self == Self::Mastodon{
+            return notifications;
+        }
+        let mut notifications: Vec<Value> = notifications.as_array().cloned().unwrap_or_default()
+            .into_iter()
+            .filter_map(|mut notification| {
+                stringify_ids(&mut notification, &["id"]);
+                let kind = match notification.get("type").and_then(|v| v.as_str()) {
+                    Some("mention") | Some("reply") => "mention",
+                    Some("update") => "update",
+                    _ => return None,
+                };
+                notification["type"] = Value::from(kind);
+                let status = notification.get_mut("status")?;
+                normalize_status(status);
+                if notification.get("account").is_none_or(|account| account.is_null()){
+                    notification["account"] = notification["status"]["account"].clone();
+                }
+                if let Some(account) = notification.get_mut("account"){
+                    stringify_ids(account, &["id"]);
+                }
+                Some(notification)
+            })
+            .collect();
+        notifications.sort_by(|a, b| compare_ids(get_id(b), get_id(a)));
+        Value::from(notifications)
+    }
+
+    // Statuses as Mastodon sends them, with string ids and the newest first
+    pub fn normalize_statuses(&self, statuses: Value) -> Value{
+        if *self == Self::Mastodon{
+            return statuses;
+        }
+        let mut statuses: Vec<Value> = statuses.as_array().cloned().unwrap_or_default();
+        statuses.iter_mut().for_each(normalize_status);
+        statuses.sort_by(|a, b| compare_ids(get_id(b), get_id(a)));
+        Value::from(statuses)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{get_max_id, Software};
+    use serde_json::json;
+
+    #[test]
+    fn detect() {
+        assert_eq!("GoToSocial".parse::<Software>().unwrap(), Software::GoToSocial);
+        assert_eq!("sharkey".parse::<Software>().unwrap(), Software::Misskey);
+        assert!("lemmy".parse::<Software>().is_err());
+        assert_eq!(Software::from_version("2.7.2 (compatible; Pleroma 2.5.0)"), Software::Pleroma);
+        assert_eq!(Software::from_version("2.7.2 (compatible; Akkoma 3.10.4)"), Software::Akkoma);
+        assert_eq!(Software::from_version("4.2.1"), Software::Mastodon);
+    }
+
+    #[test]
+    fn normalize() {
+        let notifications = json!([
+            {"id": 9, "type": "reply", "status": {"id": 90, "account": {"id": 1, "acct": "a"}}},
+            {"id": 10, "type": "pleroma:emoji_reaction", "status": {"id": 91}},
+            {"id": 11, "type": "mention", "account": {"id": "2", "acct": "b"}, "status": {"id": "92"}}
+        ]);
+        assert_eq!(Software::Mastodon.normalize_notifications(notifications.clone()), notifications);
+        let notifications = Software::Akkoma.normalize_notifications(notifications);
+        assert_eq!(notifications, json!([
+            {"id": "11", "type": "mention", "account": {"id": "2", "acct": "b"}, "status": {"id": "92"}},
+            {"id": "9", "type": "mention", "account": {"id": "1", "acct": "a"},
+                "status": {"id": "90", "account": {"id": "1", "acct": "a"}}}
+        ]));
+        let statuses = Software::GoToSocial.normalize_statuses(json!([{"id": "01A"}, {"id": "01B"}]));
+        assert_eq!(statuses, json!([{"id": "01B"}, {"id": "01A"}]));
+        assert_eq!(get_max_id(&json!([{"id": 9}, {"id": 10}, {"id": "8"}])).as_deref(), Some("10"));
+        assert_eq!(get_max_id(&json!([])), None);
+    }
+}
@@ -0,0 +1,228 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::collections::HashSet;
+use super::{Error, Zinc};
+
+pub(super) const TOP_CONTRIBUTORS: usize = 10;
+pub(super) const TOP_IDEAS: usize = 10;
+const MAX_HITS: usize = 1000;
+
+#[derive(Debug, PartialEq)]
+pub struct WeekCount{
+    pub week: String,
+    pub category: String,
+    pub total: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Contributor{
+    pub account: String,
+    pub total: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Item{
+    pub notification_id: String,
+    pub account: String,
+    pub message: String,
+    pub status_url: Option<String>,
+    pub timestamp: String,
+}
+
+// Historical feedback statistics built from the events published to Zinc
+#[derive(Debug)]
+pub struct Stats{
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub per_week: Vec<WeekCount>,
+    pub top_contributors: Vec<Contributor>,
+    pub unanswered: Vec<Item>,
+    pub ideas: Vec<Item>,
+}
+
+fn get_string(hit: &Value, key: &str) -> Option<String>{
+    match hit.get(key)? {
+        Value::String(value) => Some(value.to_string()),
+        Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+fn get_u64(hit: &Value, key: &str) -> u64{
+    hit.get(key).and_then(|v| v.as_u64()).unwrap_or_default()
+}
+
+// Weeks come back either as a timestamp in microseconds or as a date string
+fn get_week(hit: &Value) -> String{
+    match hit.get("week") {
+        Some(Value::Number(micros)) => Utc.timestamp_micros(micros.as_i64().unwrap_or_default())
+            .single()
+            .map(|week| week.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+        Some(Value::String(week)) => week.chars().take(10).collect(),
+        _ => "".to_string(),
+    }
+}
+
+// Deliveries are flattened to a JSON string by OpenObserve
+fn is_delivered(hit: &Value) -> bool{
+    let deliveries = match hit.get("deliveries") {
+        Some(Value::String(deliveries)) => serde_json::from_str(deliveries).unwrap_or(Value::Null),
+        Some(deliveries) => deliveries.clone(),
+        None => Value::Null,
+    };
+    deliveries.as_array()
+        .map(|deliveries| deliveries.iter()
+            .any(|delivery| delivery.get("delivered").and_then(|v| v.as_bool()) == Some(true)))
+        .unwrap_or_default()
+}
+
+fn get_item(hit: &Value) -> Option<Item>{
+    Some(Item{
+        notification_id: get_string(hit, "notification_id")?,
+        account: get_string(hit, "account").unwrap_or_default(),
+        message: get_string(hit, "message").unwrap_or_default(),
+        status_url: get_string(hit, "status_url"),
+        timestamp: get_string(hit, "timestamp").unwrap_or_default(),
+    })
+}
+
+impl Stats{
+    pub async fn query(zinc: &Zinc, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Stats, Error>{
+        let indice = zinc.get_indice();
+        let start_time = from.timestamp_micros();
+        let end_time = to.timestamp_micros();
+        let per_week = zinc.search(&format!(
+            "SELECT date_trunc('week', to_timestamp_micros(_timestamp)) AS week, category, count(*) AS total \
+             FROM \"{}\" WHERE type = 'mention' GROUP BY week, category ORDER BY week, category",
+            indice), start_time, end_time, MAX_HITS).await?;
+        let top_contributors = zinc.search(&format!(
+            "SELECT account, count(*) AS total FROM \"{}\" \
+             WHERE type = 'mention' AND category <> 'mencion' \
+             GROUP BY account ORDER BY total DESC LIMIT {}",
+            indice, TOP_CONTRIBUTORS), start_time, end_time, TOP_CONTRIBUTORS).await?;
+        let questions = zinc.search(&format!(
+            "SELECT notification_id, account, message, status_url, timestamp FROM \"{}\" \
+             WHERE type = 'mention' AND category = 'pregunta' ORDER BY timestamp",
+            indice), start_time, end_time, MAX_HITS).await?;
+        let replies = zinc.search(&format!(
+            "SELECT notification_id, deliveries FROM \"{}\" \
+             WHERE type = 'command' AND command IN ('reply', 'notify_answered')",
+            indice), start_time, end_time, MAX_HITS).await?;
+        let ideas = zinc.search(&format!(
+            "SELECT notification_id, account, message, status_url, timestamp FROM \"{}\" \
+             WHERE type = 'mention' AND category = 'idea' ORDER BY timestamp DESC LIMIT {}",
+            indice, TOP_IDEAS), start_time, end_time, TOP_IDEAS).await?;
+        Ok(Self::from_hits(from, to, &per_week, &top_contributors, &questions, &replies, &ideas))
+    }
+
+    pub fn from_hits(from: DateTime<Utc>, to: DateTime<Utc>, per_week: &[Value],
+            top_contributors: &[Value], questions: &[Value], replies: &[Value], ideas: &[Value]) -> Stats{
+        let answered: HashSet<String> = replies.iter()
+            .filter(|hit| is_delivered(hit))
+            .filter_map(|hit| get_string(hit, "notification_id"))
+            .collect();
+        Stats {
+            from,
+            to,
+            per_week: per_week.iter()
+                .map(|hit| WeekCount{
+                    week: get_week(hit),
+                    category: get_string(hit, "category").unwrap_or_default(),
+                    total: get_u64(hit, "total"),
+                })
+                .collect(),
+            top_contributors: top_contributors.iter()
+                .map(|hit| Contributor{
+                    account: get_string(hit, "account").unwrap_or_default(),
+                    total: get_u64(hit, "total"),
+                })
+                .collect(),
+            unanswered: questions.iter()
+                .filter_map(get_item)
+                .filter(|question| !answered.contains(&question.notification_id))
+                .collect(),
+            ideas: ideas.iter()
+                .filter_map(get_item)
+                .collect(),
+        }
+    }
+
+    pub fn per_category(&self) -> Vec<(String, u64)>{
+        let mut totals: Vec<(String, u64)> = Vec::new();
+        for count in self.per_week.iter(){
+            match totals.iter_mut().find(|(category, _)| category == &count.category){
+                Some((_, total)) => *total += count.total,
+                None => totals.push((count.category.to_string(), count.total)),
+            }
+        }
+        totals
+    }
+
+    pub fn to_text(&self) -> String{
+        let mut text = format!("Feedback from {} to {}\n",
+            self.from.format("%Y-%m-%d"), self.to.format("%Y-%m-%d"));
+        text.push_str("\nPer category and week:\n");
+        for count in self.per_week.iter(){
+            text.push_str(&format!("  {} {:<12} {}\n", count.week, count.category, count.total));
+        }
+        text.push_str("\nTop contributors:\n");
+        for contributor in self.top_contributors.iter(){
+            text.push_str(&format!("  {:<40} {}\n", contributor.account, contributor.total));
+        }
+        text.push_str("\nLatest ideas:\n");
+        for idea in self.ideas.iter(){
+            text.push_str(&format!("  [{}] {}: {}\n", idea.notification_id, idea.account,
+                idea.message.replace('\n', " ")));
+        }
+        text.push_str("\nUnanswered questions:\n");
+        for question in self.unanswered.iter(){
+            text.push_str(&format!("  [{}] {}: {}\n", question.notification_id, question.account,
+                question.message.replace('\n', " ")));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{Contributor, Stats, WeekCount};
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    #[test]
+    fn from_hits() {
+        let from = Utc.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2023, 7, 31, 0, 0, 0).unwrap();
+        let per_week = vec![
+            json!({"week": 1688947200000000i64, "category": "idea", "total": 3}),
+            json!({"week": "2023-07-17T00:00:00", "category": "pregunta", "total": 2}),
+        ];
+        let top_contributors = vec![json!({"account": "@atareao@mastodon.social", "total": 4})];
+        let questions = vec![
+            json!({"notification_id": "1", "account": "@a", "message": "¿Uno?", "timestamp": "2023-07-17T10:00:00Z"}),
+            json!({"notification_id": "2", "account": "@b", "message": "¿Dos?", "timestamp": "2023-07-18T10:00:00Z"}),
+            json!({"notification_id": "3", "account": "@c", "message": "¿Tres?", "timestamp": "2023-07-19T10:00:00Z"}),
+        ];
+        let replies = vec![
+            json!({"notification_id": "1", "deliveries": "[{\"sink\":\"mastodon\",\"delivered\":true}]"}),
+            json!({"notification_id": "2", "deliveries": [{"sink": "mastodon", "delivered": false}]}),
+        ];
+        let ideas = vec![json!({"notification_id": "4", "account": "@d", "message": "#idea", "timestamp": "2023-07-19T10:00:00Z"})];
+        let stats = Stats::from_hits(from, to, &per_week, &top_contributors, &questions, &replies, &ideas);
+        assert_eq!(stats.per_week, vec![
+            WeekCount{week: "2023-07-10".to_string(), category: "idea".to_string(), total: 3},
+            WeekCount{week: "2023-07-17".to_string(), category: "pregunta".to_string(), total: 2},
+        ]);
+        assert_eq!(stats.top_contributors, vec![
+            Contributor{account: "@atareao@mastodon.social".to_string(), total: 4},
+        ]);
+        let unanswered: Vec<&str> = stats.unanswered.iter()
+            .map(|question| question.notification_id.as_str())
+            .collect();
+        assert_eq!(unanswered, vec!["2", "3"]);
+        assert_eq!(stats.ideas.len(), 1);
+        assert_eq!(stats.per_category(), vec![("idea".to_string(), 3), ("pregunta".to_string(), 2)]);
+        assert!(stats.to_text().contains("[3] @c: ¿Tres?"));
+    }
+}
@@ -0,0 +1,505 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use tracing::{debug, info};
+use super::stats::{TOP_CONTRIBUTORS, TOP_IDEAS};
+use super::{Delivery, Error, Mention, Stats};
+
+// Every migration is applied once, in order, and the number of applied
+// migrations is kept in `PRAGMA user_version`
+const MIGRATIONS: [&str; 5] = [
+    "CREATE TABLE mentions(
+        notification_id TEXT PRIMARY KEY,
+        status_id TEXT NOT NULL,
+        status_url TEXT,
+        account TEXT NOT NULL,
+        category TEXT NOT NULL,
+        message TEXT NOT NULL,
+        content TEXT NOT NULL,
+        language TEXT,
+        visibility TEXT,
+        created_at TEXT NOT NULL,
+        feedback_id TEXT,
+        reply_id TEXT,
+        state TEXT NOT NULL DEFAULT 'received',
+        processed_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE INDEX mentions_processed_at ON mentions(processed_at);
+    CREATE TABLE deliveries(
+        notification_id TEXT NOT NULL REFERENCES mentions(notification_id) ON DELETE CASCADE,
+        sink TEXT NOT NULL,
+        delivered INTEGER NOT NULL,
+        error TEXT,
+        remote_id TEXT,
+        updated_at TEXT NOT NULL,
+        PRIMARY KEY(notification_id, sink)
+    );",
+    "ALTER TABLE mentions ADD COLUMN profile TEXT NOT NULL DEFAULT 'default';",
+    "CREATE INDEX mentions_status_id ON mentions(status_id);",
+    "ALTER TABLE mentions ADD COLUMN edited_at TEXT;",
+    // Notification ids are only unique within an instance, so the key of a
+    // mention includes its profile. Tables are renamed once the new ones
+    // reference each other.
+    "CREATE TABLE mentions_new(
+        profile TEXT NOT NULL DEFAULT 'default',
+        notification_id TEXT NOT NULL,
+        status_id TEXT NOT NULL,
+        status_url TEXT,
+        account TEXT NOT NULL,
+        category TEXT NOT NULL,
+        message TEXT NOT NULL,
+        content TEXT NOT NULL,
+        language TEXT,
+        visibility TEXT,
+        created_at TEXT NOT NULL,
+        feedback_id TEXT,
+        reply_id TEXT,
+        state TEXT NOT NULL DEFAULT 'received',
+        processed_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        edited_at TEXT,
+        PRIMARY KEY(profile, notification_id)
+    );
+    INSERT INTO mentions_new(profile, notification_id, status_id, status_url, account, category, message,
+        content, language, visibility, created_at, feedback_id, reply_id, state, processed_at, updated_at, edited_at)
+        SELECT profile, notification_id, status_id, status_url, account, category, message, content, language,
+            visibility, created_at, feedback_id, reply_id, state, processed_at, updated_at, edited_at
+        FROM mentions;
+    CREATE TABLE deliveries_new(
+        profile TEXT NOT NULL DEFAULT 'default',
+        notification_id TEXT NOT NULL,
+        sink TEXT NOT NULL,
+        delivered INTEGER NOT NULL,
+        error TEXT,
+        remote_id TEXT,
+        updated_at TEXT NOT NULL,
+        PRIMARY KEY(profile, notification_id, sink),
+        FOREIGN KEY(profile, notification_id) REFERENCES mentions_new(profile, notification_id) ON DELETE CASCADE
+    );
+    INSERT INTO deliveries_new(profile, notification_id, sink, delivered, error, remote_id, updated_at)
+        SELECT mentions.profile, deliveries.notification_id, sink, delivered, error, remote_id, deliveries.updated_at
+        FROM deliveries JOIN mentions ON mentions.notification_id = deliveries.notification_id;
+    DROP TABLE deliveries;
+    DROP TABLE mentions;
+    ALTER TABLE mentions_new RENAME TO mentions;
+    ALTER TABLE deliveries_new RENAME TO deliveries;
+    CREATE INDEX mentions_processed_at ON mentions(processed_at);
+    CREATE INDEX mentions_status_id ON mentions(profile, status_id);",
+];
+
+// Lifecycle of a mention after it is stored as `received`
+// Some sink did not get the mention yet, it is retried
+pub const PENDING: &str = "pending";
+pub const APPLIED: &str = "applied";
+pub const REJECTED: &str = "rejected";
+pub const ANSWERED: &str = "answered";
+// The author deleted the status
+pub const RETRACTED: &str = "retracted";
+
+const RECORD_COLUMNS: &str = "notification_id, profile, status_id, status_url, account, category, message, \
+    feedback_id, reply_id, state, created_at, processed_at, edited_at";
+
+// Mention as recorded in the store
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record{
+    pub notification_id: String,
+    // Profile of the Mastodon account that received the mention
+    pub profile: String,
+    pub status_id: String,
+    pub status_url: Option<String>,
+    pub account: String,
+    pub category: String,
+    pub message: String,
+    pub feedback_id: Option<String>,
+    pub reply_id: Option<String>,
+    pub state: String,
+    pub created_at: String,
+    pub processed_at: String,
+    pub edited_at: Option<String>,
+}
+
+// Conditions of the mentions returned by `Store::records`
+#[derive(Debug, Default)]
+pub struct Filter{
+    pub category: Option<String>,
+    pub account: Option<String>,
+    // Answered questions and applied ideas
+    pub answered: Option<bool>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+// Local system of record for every processed mention, kept in SQLite
+pub struct Store{
+    connection: Mutex<Connection>,
+}
+
+fn now() -> String{
+    to_timestamp(Utc::now())
+}
+
+fn to_timestamp(datetime: DateTime<Utc>) -> String{
+    datetime.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn to_record(row: &Row) -> rusqlite::Result<Record>{
+    Ok(Record{
+        notification_id: row.get(0)?,
+        profile: row.get(1)?,
+        status_id: row.get(2)?,
+        status_url: row.get(3)?,
+        account: row.get(4)?,
+        category: row.get(5)?,
+        message: row.get(6)?,
+        feedback_id: row.get(7)?,
+        reply_id: row.get(8)?,
+        state: row.get(9)?,
+        created_at: row.get(10)?,
+        processed_at: row.get(11)?,
+        edited_at: row.get(12)?,
+    })
+}
+
+fn to_values(connection: &Connection, sql: &str, from: &str, to: &str, columns: &[&str]) -> Result<Vec<Value>, Error>{
+    let mut statement = connection.prepare(sql)?;
+    let rows = statement.query_map(params![from, to], |row| {
+        let mut value = json!({});
+        for (index, column) in columns.iter().enumerate(){
+            value[*column] = match row.get_ref(index)? {
+                rusqlite::types::ValueRef::Integer(number) => json!(number),
+                rusqlite::types::ValueRef::Text(text) => json!(String::from_utf8_lossy(text)),
+                _ => Value::Null,
+            };
+        }
+        Ok(value)
+    })?;
+    Ok(rows.collect::<Result<Vec<Value>, _>>()?)
+}
+
+impl Store{
+    pub fn open(path: &str) -> Result<Store, Error>{
+        info!("open: {path}");
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        let store = Self { connection: Mutex::new(connection) };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn migrate(&self) -> Result<(), Error>{
+        let mut connection = self.connection.lock().unwrap();
+        let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version){
+            debug!("migration {}", index + 1);
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+        }
+        Ok(())
+    }
+
+    // Pending mentions are not done yet
+    pub fn contains(&self, profile: &str, notification_id: &str) -> Result<bool, Error>{
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.query_row("SELECT 1 FROM mentions WHERE profile = ?1 AND notification_id = ?2 AND state <> ?3",
+            params![profile, notification_id, PENDING], |_| Ok(())).optional()?.is_some())
+    }
+
+    // Statuses can be found both as a notification and by search
+    pub fn contains_status(&self, profile: &str, status_id: &str) -> Result<bool, Error>{
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.query_row("SELECT 1 FROM mentions WHERE profile = ?1 AND status_id = ?2 LIMIT 1",
+            params![profile, status_id], |_| Ok(())).optional()?.is_some())
+    }
+
+    // Sinks that already got a mention, which are left out when it is retried
+    pub fn delivered(&self, profile: &str, notification_id: &str) -> Result<Vec<String>, Error>{
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT sink FROM deliveries WHERE profile = ?1 AND notification_id = ?2 AND delivered = 1")?;
+        let rows = statement.query_map(params![profile, notification_id], |row| row.get(0))?;
+        Ok(rows.collect::<Result<Vec<String>, _>>()?)
+    }
+
+    // Records the deliveries of a mention. It stays pending until every sink
+    // got it, and a retry only brings the deliveries of the failed sinks.
+    pub fn record(&self, profile: &str, mention: &Mention, category: &str, message: &str,
+            reply_id: Option<&str>, deliveries: &[Delivery]) -> Result<(), Error>{
+        let mut connection = self.connection.lock().unwrap();
+        let now = now();
+        let feedback_id = deliveries.iter()
+            .find(|delivery| delivery.sink == "feedback")
+            .and_then(|delivery| delivery.remote_id.clone());
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO mentions(notification_id, status_id, status_url, account, category, message,
+                content, language, visibility, created_at, feedback_id, reply_id, processed_at, updated_at, profile,
+                edited_at, state)
+             VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13, ?14, ?15, ?16)
+             ON CONFLICT(profile, notification_id) DO UPDATE SET category = ?5, message = ?6,
+                feedback_id = COALESCE(?11, feedback_id), reply_id = COALESCE(?12, reply_id), updated_at = ?13",
+            params![mention.id, mention.status_id, mention.url, format!("@{}", mention.nickname),
+                category, message, mention.content, mention.language, mention.visibility,
+                mention.created_at, feedback_id, reply_id, now, profile, mention.edited_at, PENDING])?;
+        for delivery in deliveries.iter(){
+            transaction.execute(
+                "INSERT OR REPLACE INTO deliveries(profile, notification_id, sink, delivered, error, remote_id, updated_at)
+                 VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![profile, mention.id, delivery.sink, delivery.delivered, delivery.error, delivery.remote_id, now])?;
+        }
+        transaction.execute(
+            &format!("UPDATE mentions SET state = 'received' WHERE profile = ?1 AND notification_id = ?2
+                AND state = '{}' AND NOT EXISTS(SELECT 1 FROM deliveries WHERE profile = ?1
+                AND notification_id = ?2 AND delivered = 0)", PENDING),
+            params![profile, mention.id])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, profile: &str, notification_id: &str) -> Result<Option<Record>, Error>{
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.query_row(
+            &format!("SELECT {} FROM mentions WHERE profile = ?1 AND notification_id = ?2", RECORD_COLUMNS),
+            params![profile, notification_id],
+            to_record).optional()?)
+    }
+
+    // Mentions of every profile with the notification id, which moderators
+    // write without the profile
+    pub fn find(&self, notification_id: &str) -> Result<Vec<Record>, Error>{
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            &format!("SELECT {} FROM mentions WHERE notification_id = ?1 ORDER BY profile", RECORD_COLUMNS))?;
+        let rows = statement.query_map(params![notification_id], to_record)?;
+        Ok(rows.collect::<Result<Vec<Record>, _>>()?)
+    }
+
+    // First mention recorded for a status
+    pub fn get_status(&self, profile: &str, status_id: &str) -> Result<Option<Record>, Error>{
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.query_row(
+            &format!("SELECT {} FROM mentions WHERE profile = ?1 AND status_id = ?2 ORDER BY processed_at LIMIT 1",
+                RECORD_COLUMNS),
+            params![profile, status_id],
+            to_record).optional()?)
+    }
+
+    // Id given by a sink to the mention, like the Matrix event id
+    pub fn get_remote_id(&self, profile: &str, notification_id: &str, sink: &str) -> Result<Option<String>, Error>{
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.query_row(
+            "SELECT remote_id FROM deliveries WHERE profile = ?1 AND notification_id = ?2 AND sink = ?3",
+            params![profile, notification_id, sink],
+            |row| row.get(0)).optional()?.flatten())
+    }
+
+    // Keeps the edit of a status made by its author
+    pub fn revise(&self, profile: &str, notification_id: &str, mention: &Mention, category: &str,
+            message: &str) -> Result<(), Error>{
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "UPDATE mentions SET category = ?1, message = ?2, content = ?3, edited_at = ?4, updated_at = ?5
+             WHERE profile = ?6 AND notification_id = ?7",
+            params![category, message, mention.content, mention.edited_at, now(), profile, notification_id])?;
+        Ok(())
+    }
+
+    // Mentions matching the filter, oldest first
+    pub fn records(&self, filter: &Filter) -> Result<Vec<Record>, Error>{
+        let connection = self.connection.lock().unwrap();
+        let mut conditions = vec!["1 = 1".to_string()];
+        let mut values = Vec::new();
+        if let Some(category) = &filter.category{
+            values.push(category.to_string());
+            conditions.push(format!("category = ?{}", values.len()));
+        }
+        if let Some(account) = &filter.account{
+            values.push(format!("@{}", account.trim_start_matches('@')));
+            conditions.push(format!("account = ?{}", values.len()));
+        }
+        if let Some(answered) = filter.answered{
+            let operator = if answered {"IN"} else {"NOT IN"};
+            conditions.push(format!("state {} ('{}', '{}')", operator, ANSWERED, APPLIED));
+        }
+        if let Some(from) = filter.from{
+            values.push(to_timestamp(from));
+            conditions.push(format!("processed_at >= ?{}", values.len()));
+        }
+        if let Some(to) = filter.to{
+            values.push(to_timestamp(to));
+            conditions.push(format!("processed_at < ?{}", values.len()));
+        }
+        let mut statement = connection.prepare(&format!(
+            "SELECT {} FROM mentions WHERE {} ORDER BY processed_at, notification_id",
+            RECORD_COLUMNS, conditions.join(" AND ")))?;
+        let rows = statement.query_map(params_from_iter(values.iter()), to_record)?;
+        Ok(rows.collect::<Result<Vec<Record>, _>>()?)
+    }
+
+    pub fn set_state(&self, profile: &str, notification_id: &str, state: &str) -> Result<(), Error>{
+        self.update(profile, notification_id, "state", state)
+    }
+
+    pub fn set_category(&self, profile: &str, notification_id: &str, category: &str) -> Result<(), Error>{
+        self.update(profile, notification_id, "category", category)
+    }
+
+    fn update(&self, profile: &str, notification_id: &str, column: &str, value: &str) -> Result<(), Error>{
+        let connection = self.connection.lock().unwrap();
+        let updated = connection.execute(
+            &format!("UPDATE mentions SET {} = ?1, updated_at = ?2 WHERE profile = ?3 AND notification_id = ?4",
+                column),
+            params![value, now(), profile, notification_id])?;
+        if updated == 0{
+            return Err(format!("Unknown notification {}", notification_id).into());
+        }
+        Ok(())
+    }
+
+    // Same statistics as `Stats::query`, from the mentions processed in the period
+    pub fn stats(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Stats, Error>{
+        let connection = self.connection.lock().unwrap();
+        let (start, end) = (to_timestamp(from), to_timestamp(to));
+        let period = format!("processed_at >= ?1 AND processed_at < ?2 AND state <> '{}'", RETRACTED);
+        let period = period.as_str();
+        let per_week = to_values(&connection, &format!(
+            "SELECT date(processed_at, 'weekday 0', '-6 days') AS week, category, count(*) AS total
+             FROM mentions WHERE {} GROUP BY week, category ORDER BY week, category", period),
+            &start, &end, &["week", "category", "total"])?;
+        let top_contributors = to_values(&connection, &format!(
+            "SELECT account, count(*) AS total FROM mentions WHERE {} AND category <> 'mencion'
+             GROUP BY account ORDER BY total DESC LIMIT {}", period, TOP_CONTRIBUTORS),
+            &start, &end, &["account", "total"])?;
+        let item = ["notification_id", "account", "message", "status_url", "timestamp"];
+        let questions = to_values(&connection, &format!(
+            "SELECT notification_id, account, message, status_url, processed_at FROM mentions
+             WHERE {} AND category = 'pregunta' AND state <> '{}' ORDER BY processed_at", period, ANSWERED),
+            &start, &end, &item)?;
+        let ideas = to_values(&connection, &format!(
+            "SELECT notification_id, account, message, status_url, processed_at FROM mentions
+             WHERE {} AND category = 'idea' ORDER BY processed_at DESC LIMIT {}", period, TOP_IDEAS),
+            &start, &end, &item)?;
+        Ok(Stats::from_hits(from, to, &per_week, &top_contributors, &questions, &[], &ideas))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{Filter, Store, ANSWERED, MIGRATIONS, PENDING, RETRACTED};
+    use rusqlite::Connection;
+    use std::sync::Mutex;
+    use crate::models::{fixtures, Delivery, Mention};
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    fn get_mention(id: &str, content: &str) -> Mention{
+        fixtures::get_mention(id, json!({"content": content}))
+    }
+
+    #[test]
+    fn record_and_query() {
+        let store = Store::open(":memory:").unwrap();
+        let deliveries = vec![
+            Delivery::new("feedback", &Ok::<String, String>("42".to_string())).with_remote_id(Some("42".to_string())),
+            Delivery::new("matrix", &Err::<String, String>("timeout".to_string())),
+        ];
+        store.record("default", &get_mention("1", "#pregunta ¿Uno?"), "pregunta", "¿Uno?", Some("100"), &deliveries).unwrap();
+        store.record("podcast", &get_mention("2", "#pregunta ¿Dos?"), "pregunta", "¿Dos?", None, &[]).unwrap();
+        store.record("default", &get_mention("3", "#idea Rust"), "idea", "Rust", None, &[]).unwrap();
+        assert!(store.contains("default", "3").unwrap());
+        assert!(!store.contains("podcast", "1").unwrap());
+        assert!(!store.contains("default", "5").unwrap());
+        assert!(store.contains_status("default", "91").unwrap());
+        assert!(!store.contains_status("podcast", "91").unwrap());
+        assert!(!store.contains_status("default", "1").unwrap());
+        let record = store.get("default", "1").unwrap().unwrap();
+        assert_eq!(record.feedback_id.as_deref(), Some("42"));
+        assert_eq!(record.reply_id.as_deref(), Some("100"));
+        let error: Option<String> = store.connection.lock().unwrap().query_row(
+            "SELECT error FROM deliveries WHERE notification_id = '1' AND sink = 'matrix'", [], |row| row.get(0)).unwrap();
+        assert_eq!(error.as_deref(), Some("timeout"));
+        // The mention waits for Matrix
+        assert_eq!(record.state, PENDING);
+        assert!(!store.contains("default", "1").unwrap());
+        assert_eq!(store.delivered("default", "1").unwrap(), vec!["feedback".to_string()]);
+        let retry = vec![Delivery::new("matrix", &Ok::<String, String>("$event".to_string()))];
+        store.record("default", &get_mention("1", "#pregunta ¿Uno?"), "pregunta", "¿Uno?", None, &retry).unwrap();
+        let record = store.get("default", "1").unwrap().unwrap();
+        assert_eq!(record.state, "received");
+        assert!(store.contains("default", "1").unwrap());
+        assert_eq!(record.reply_id.as_deref(), Some("100"));
+        assert_eq!(store.delivered("default", "1").unwrap().len(), 2);
+        assert_eq!(store.get("podcast", "2").unwrap().unwrap().state, "received");
+        assert_eq!(store.get_remote_id("default", "1", "feedback").unwrap().as_deref(), Some("42"));
+        assert_eq!(store.get_remote_id("default", "1", "matrix").unwrap(), None);
+
+        let mut edited = get_mention("3", "#idea Rust y Go");
+        edited.edited_at = Some("2023-07-25T11:00:00.000Z".to_string());
+        store.revise("default", "3", &edited, "idea", "Rust y Go").unwrap();
+        let record = store.get_status("default", "93").unwrap().unwrap();
+        assert_eq!(record.message, "Rust y Go");
+        assert_eq!(record.edited_at, edited.edited_at);
+        // Retracted mentions are left out of the statistics
+        store.record("default", &get_mention("4", "#idea Borrada"), "idea", "Borrada", None, &[]).unwrap();
+        store.set_state("default", "4", RETRACTED).unwrap();
+
+        store.set_state("default", "1", ANSWERED).unwrap();
+        assert!(store.set_state("podcast", "1", ANSWERED).is_err());
+        let stats = store.stats(Utc::now() - Duration::days(1), Utc::now() + Duration::days(1)).unwrap();
+        assert_eq!(stats.per_category(), vec![("idea".to_string(), 1), ("pregunta".to_string(), 2)]);
+        assert_eq!(stats.top_contributors[0].total, 3);
+        assert_eq!(stats.unanswered.len(), 1);
+        assert_eq!(stats.unanswered[0].notification_id, "2");
+        assert_eq!(stats.ideas[0].message, "Rust y Go");
+
+        let filter = Filter{category: Some("pregunta".to_string()), answered: Some(false), ..Default::default()};
+        let records = store.records(&filter).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].notification_id, "2");
+        assert_eq!(records[0].profile, "podcast");
+        let filter = Filter{account: Some("atareao@mastodon.social".to_string()), ..Default::default()};
+        assert_eq!(store.records(&filter).unwrap().len(), 4);
+        let filter = Filter{from: Some(Utc::now() + Duration::days(1)), ..Default::default()};
+        assert!(store.records(&filter).unwrap().is_empty());
+    }
+
+    #[test]
+    fn migrate_once() {
+        let path = std::env::temp_dir().join(format!("watchdog-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        Store::open(path).unwrap().record("default", &get_mention("1", "hola"), "mencion", "hola", None, &[]).unwrap();
+        assert!(Store::open(path).unwrap().contains("default", "1").unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn same_notification_in_two_profiles() {
+        let store = Store::open(":memory:").unwrap();
+        store.record("default", &get_mention("1", "#idea Rust"), "idea", "Rust", None, &[]).unwrap();
+        store.record("podcast", &get_mention("1", "#idea Go"), "idea", "Go", None, &[]).unwrap();
+        assert_eq!(store.get("default", "1").unwrap().unwrap().message, "Rust");
+        assert_eq!(store.get("podcast", "1").unwrap().unwrap().message, "Go");
+        assert_eq!(store.find("1").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn migrate_profile_key() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(&MIGRATIONS[..4].join("\n")).unwrap();
+        connection.execute_batch(
+            "INSERT INTO mentions(notification_id, status_id, account, category, message, content,
+                created_at, processed_at, updated_at, profile)
+             VALUES('1', '91', '@a', 'idea', 'Rust', 'Rust', 'x', 'x', 'x', 'podcast');
+             INSERT INTO deliveries(notification_id, sink, delivered, remote_id, updated_at)
+             VALUES('1', 'matrix', 1, '$event', 'x');
+             PRAGMA user_version = 4;").unwrap();
+        let store = Store{connection: Mutex::new(connection)};
+        store.migrate().unwrap();
+        assert!(store.contains("podcast", "1").unwrap());
+        assert_eq!(store.get_remote_id("podcast", "1", "matrix").unwrap().as_deref(), Some("$event"));
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::fs;
+use tracing::{debug, info};
+use super::{compare_ids, unqualify, Error};
+
+const MAX_STATUSES: usize = 5000;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Thread{
+    pub root: String,
+    pub last: String,
+}
+
+// Maps every Mastodon status seen in a conversation (mentions and our own
+// replies), qualified by its profile, to the Matrix thread that holds it.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Threads{
+    #[serde(default)]
+    statuses: BTreeMap<String, Thread>,
+}
+
+impl Threads{
+    pub fn read(filename: &str) -> Result<Threads, Error>{
+        info!("read");
+        if Path::new(filename).exists(){
+            let data = fs::read_to_string(filename)?;
+            debug!("{}", data);
+            Ok(toml::from_str(&data)?)
+        }else{
+            let threads = Self::default();
+            threads.save(filename)?;
+            Ok(threads)
+        }
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), std::io::Error>{
+        info!("save");
+        let toml = toml::to_string(&self).unwrap();
+        fs::write(filename, toml)
+    }
+
+    pub fn get(&self, status_id: &str) -> Option<&Thread>{
+        self.statuses.get(status_id)
+    }
+
+    pub fn insert(&mut self, status_id: &str, thread: Thread){
+        self.statuses.insert(status_id.to_string(), thread);
+        while self.statuses.len() > MAX_STATUSES{
+            let oldest = self.statuses.keys()
+                .min_by(|a, b| compare_ids(unqualify(a), unqualify(b)))
+                .cloned()
+                .unwrap();
+            self.statuses.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{Thread, Threads};
+
+    #[test]
+    fn insert_and_get() {
+        let mut threads = Threads::default();
+        let thread = Thread{root: "$root".to_string(), last: "$root".to_string()};
+        threads.insert("default/110758642668166239", thread.clone());
+        assert_eq!(threads.get("default/110758642668166239"), Some(&thread));
+        assert_eq!(threads.get("podcast/110758642668166239"), None);
+        let data = toml::to_string(&threads).unwrap();
+        let threads: Threads = toml::from_str(&data).unwrap();
+        assert_eq!(threads.get("default/110758642668166239"), Some(&thread));
+    }
+}
@@ -0,0 +1,199 @@
+use regex::{Captures, Regex};
+use reqwest::Method;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::fs;
+use tracing::{debug, info};
+use super::{Error, HttpSink, Mention};
+
+// Outgoing webhook configured in the webhooks file, like
+//
+// [[webhook]]
+// name = "slack"
+// url = "https://hooks.slack.com/services/..."
+// categories = ["idea", "pregunta"]
+// [webhook.headers]
+// X-Token = "secret"
+// [webhook.payload]
+// text = "Nueva {{category}} de @{{nickname}}: {{text}} {{url}}"
+//
+// Every `{{field}}` in the strings of the payload is replaced by the value of
+// the mention field with that name, in a single pass, so placeholders in the
+// values are kept as they are.
+#[derive(Debug, Deserialize)]
+pub struct Webhook{
+    pub name: String,
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // Empty means every category
+    #[serde(default)]
+    pub categories: Vec<String>,
+    pub payload: Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct WebhooksFile{
+    #[serde(default)]
+    webhook: Vec<Webhook>,
+}
+
+fn default_method() -> String{
+    "POST".to_string()
+}
+
+pub fn read_webhooks(filename: &str) -> Result<Vec<Webhook>, Error>{
+    info!("read");
+    if !Path::new(filename).exists(){
+        return Ok(Vec::new());
+    }
+    let data = fs::read_to_string(filename)?;
+    let file: WebhooksFile = toml::from_str(&data)?;
+    for webhook in file.webhook.iter(){
+        Method::from_str(&webhook.method.to_uppercase())
+            .map_err(|_| format!("Webhook {}: invalid method {}", webhook.name, webhook.method))?;
+    }
+    Ok(file.webhook)
+}
+
+pub fn get_fields(mention: &Mention, category: &str, text: &str) -> HashMap<&'static str, String>{
+    HashMap::from([
+        ("id", mention.id.to_string()),
+        ("status_id", mention.status_id.to_string()),
+        ("url", mention.url.clone().unwrap_or_default()),
+        ("category", category.to_string()),
+        ("name", mention.name.to_string()),
+        ("nickname", mention.nickname.to_string()),
+        ("content", mention.content.to_string()),
+        ("text", text.to_string()),
+        ("created_at", mention.created_at.to_string()),
+        ("language", mention.language.clone().unwrap_or_default()),
+        ("visibility", mention.visibility.clone().unwrap_or_default()),
+    ])
+}
+
+fn render_value(template: &Value, fields: &HashMap<&'static str, String>) -> Value{
+    match template {
+        Value::String(template) => {
+            let field = Regex::new(r"\{\{(\w+)\}\}").unwrap();
+            Value::String(field.replace_all(template, |captures: &Captures| {
+                fields.get(&captures[1])
+                    .cloned()
+                    .unwrap_or_else(|| captures[0].to_string())
+            }).to_string())
+        },
+        Value::Array(items) => Value::Array(items.iter()
+            .map(|item| render_value(item, fields))
+            .collect()),
+        Value::Object(map) => Value::Object(map.iter()
+            .map(|(key, value)| (key.to_string(), render_value(value, fields)))
+            .collect()),
+        other => other.clone(),
+    }
+}
+
+impl Webhook{
+    pub fn accepts(&self, category: &str) -> bool{
+        self.categories.is_empty() || self.categories.iter().any(|c| c == category)
+    }
+
+    pub fn render(&self, fields: &HashMap<&'static str, String>) -> Value{
+        render_value(&self.payload, fields)
+    }
+
+    pub async fn send(&self, sink: &HttpSink, fields: &HashMap<&'static str, String>) -> Result<String, Error>{
+        debug!("webhook: {}", self.name);
+        let method = Method::from_str(&self.method.to_uppercase())?;
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (key, value) in self.headers.iter(){
+            headers.insert(HeaderName::from_str(key)?, HeaderValue::from_str(value)?);
+        }
+        let body = serde_json::to_vec(&self.render(fields))?;
+        sink.send(method, &self.url, headers, body).await
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{WebhooksFile, get_fields};
+    use crate::models::{fixtures, HttpSink, Mention};
+    use serde_json::json;
+    use std::time::Duration;
+    use wiremock::matchers::{body_json, header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const WEBHOOKS: &str = r#"
+        [[webhook]]
+        name = "slack"
+        url = "http://localhost/slack"
+        categories = ["idea"]
+        [webhook.headers]
+        X-Token = "secret"
+        [webhook.payload]
+        text = "Nueva {{category}} de @{{nickname}}: {{text}}"
+
+        [[webhook]]
+        name = "discord"
+        url = "http://localhost/discord"
+        method = "put"
+        [webhook.payload]
+        content = "{{text}}"
+        embeds = [{ url = "{{url}}", title = "{{id}}" }]
+    "#;
+
+    fn get_mention() -> Mention{
+        fixtures::get_mention("123", json!({
+            "id": "456",
+            "url": "https://mastodon.social/@atareao/456",
+            "content": "<p>\"Hablar\" de #idea</p>"
+        }))
+    }
+
+    #[tokio::test]
+    async fn render_and_send() {
+        let mut file: WebhooksFile = toml::from_str(WEBHOOKS).unwrap();
+        let fields = get_fields(&get_mention(), "idea", "\"Hablar\" de #idea");
+        let discord = file.webhook.pop().unwrap();
+        assert!(discord.accepts("pregunta"));
+        assert_eq!(discord.render(&fields), json!({
+            "content": "\"Hablar\" de #idea",
+            "embeds": [{"url": "https://mastodon.social/@atareao/456", "title": "123"}]
+        }));
+        let mut slack = file.webhook.pop().unwrap();
+        assert!(!slack.accepts("pregunta"));
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("X-Token", "secret"))
+            .and(body_json(json!({"text": "Nueva idea de @atareao@mastodon.social: \"Hablar\" de #idea"})))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        slack.url = server.uri();
+        let sink = HttpSink::new(0, Duration::from_secs(1), Duration::from_secs(5));
+        assert_eq!(slack.send(&sink, &fields).await.unwrap(), "ok");
+    }
+
+    #[test]
+    fn render_placeholders_in_values() {
+        let mut file: WebhooksFile = toml::from_str(WEBHOOKS).unwrap();
+        let mut mention = get_mention();
+        mention.content = "Prueba {{url}} y {{nickname}}".to_string();
+        let fields = get_fields(&mention, "idea", &mention.content);
+        let discord = file.webhook.pop().unwrap();
+        assert_eq!(discord.render(&fields), json!({
+            "content": "Prueba {{url}} y {{nickname}}",
+            "embeds": [{"url": "https://mastodon.social/@atareao/456", "title": "123"}]
+        }));
+        let slack = file.webhook.pop().unwrap();
+        assert_eq!(slack.render(&fields)["text"],
+                   json!("Nueva idea de @atareao@mastodon.social: Prueba {{url}} y {{nickname}}"));
+    }
+}
@@ -1,45 +1,74 @@
-use serde_json::Value;
-use reqwest::{Client, Response};
+use serde_json::{json, Value};
+use reqwest::Client;
 use reqwest::header::{HeaderMap, HeaderValue, HeaderName};
 use std::str::FromStr;
+use std::time::Duration;
 
 use super::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Zinc{
     url: String,
-    token: String,
+    search_url: String,
+    indice: String,
+    client: Client,
 }
 
 impl Zinc{
     pub fn new(base_url: &str, indice: &str, token: &str) -> Self{
-        Self {
-            url: format!("https://{}/api/default/{}/_json", base_url, indice),
-            token: token.to_string(),
-        }
-    }
-
-    pub async fn publish(&self, body: &Value) -> Result<String, Error>{
-        self.post(&self.url, body).await
-    }
-
-    async fn post(&self, url: &str, body: &Value)->Result<String, Error>{
         let mut header_map = HeaderMap::new();
         header_map.insert(HeaderName::from_str("Content-type").unwrap(),
                           HeaderValue::from_str("application/json").unwrap());
         header_map.insert(HeaderName::from_str("Accept").unwrap(),
                           HeaderValue::from_str("application/json").unwrap());
         header_map.insert(HeaderName::from_str("Authorization").unwrap(),
-                          HeaderValue::from_str(&format!("Basic {}", self.token)).unwrap());
+                          HeaderValue::from_str(&format!("Basic {}", token)).unwrap());
         let client = Client::builder()
             .default_headers(header_map)
+            .timeout(Duration::from_secs(30))
             .build()
             .unwrap();
+        Self {
+            url: format!("https://{}/api/default/{}/_json", base_url, indice),
+            search_url: format!("https://{}/api/default/_search", base_url),
+            indice: indice.to_string(),
+            client,
+        }
+    }
+
+    pub async fn publish(&self, body: &Value) -> Result<String, Error>{
+        self.post(&self.url, body).await
+    }
+
+    pub fn get_indice(&self) -> &str{
+        &self.indice
+    }
+
+    // Runs a SQL query between two timestamps in microseconds and returns the hits
+    pub async fn search(&self, sql: &str, start_time: i64, end_time: i64, size: usize) -> Result<Vec<Value>, Error>{
+        let body = json!({
+            "query": {
+                "sql": sql,
+                "start_time": start_time,
+                "end_time": end_time,
+                "from": 0,
+                "size": size
+            }
+        });
+        let response: Value = serde_json::from_str(&self.post(&self.search_url, &body).await?)?;
+        Ok(response.get("hits")
+            .and_then(|hits| hits.as_array())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn post(&self, url: &str, body: &Value)->Result<String, Error>{
         let content = serde_json::to_string(body).unwrap();
-        Ok(client.post(url)
+        Ok(self.client.post(url)
             .body(content)
             .send()
             .await?
+            .error_for_status()?
             .text()
             .await?)
     }
@@ -0,0 +1,223 @@
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+use super::Zinc;
+
+// Accumulates records and sends them to Zinc in batches, either when the
+// batch is full or when the flush interval expires. Batches that can not be
+// delivered are spilled to disk and retried on the next flush, keeping at
+// most max_spool records. Batches that Zinc rejects are dropped.
+#[derive(Debug)]
+pub struct ZincBuffer{
+    zinc: Zinc,
+    records: Mutex<Vec<Value>>,
+    batch_size: usize,
+    retries: u32,
+    spool: PathBuf,
+    max_spool: usize,
+    notify: Notify,
+    flushing: tokio::sync::Mutex<()>,
+}
+
+impl ZincBuffer{
+    pub fn new(zinc: Zinc, batch_size: usize, retries: u32, spool: &str, max_spool: usize) -> Self{
+        Self {
+            zinc,
+            records: Mutex::new(Vec::new()),
+            batch_size: batch_size.max(1),
+            retries,
+            spool: PathBuf::from(spool),
+            max_spool,
+            notify: Notify::new(),
+            flushing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn push(&self, body: &Value){
+        let mut records = self.records.lock().unwrap();
+        match body {
+            Value::Array(items) => records.extend(items.iter().cloned()),
+            other => records.push(other.clone()),
+        }
+        if records.len() >= self.batch_size{
+            self.notify.notify_one();
+        }
+    }
+
+    pub fn pending(&self) -> usize{
+        self.records.lock().unwrap().len()
+    }
+
+    #[cfg(test)]
+    pub fn take(&self) -> Vec<Value>{
+        std::mem::take(&mut *self.records.lock().unwrap())
+    }
+
+    pub fn spawn(self: &Arc<Self>, interval: Duration) -> JoinHandle<()>{
+        let buffer = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {},
+                    _ = buffer.notify.notified() => {},
+                }
+                let delivered = buffer.flush().await;
+                if delivered > 0{
+                    debug!("Zinc: {} records delivered", delivered);
+                }
+            }
+        })
+    }
+
+    // Returns the number of records delivered
+    pub async fn flush(&self) -> usize{
+        let _guard = self.flushing.lock().await;
+        let mut delivered = 0;
+        let spooled = read_spool(&self.spool);
+        if !spooled.is_empty(){
+            let chunks: Vec<&[Value]> = spooled.chunks(self.batch_size).collect();
+            for (index, chunk) in chunks.iter().enumerate(){
+                match self.send(chunk).await{
+                    Outcome::Delivered => delivered += chunk.len(),
+                    Outcome::Rejected => {},
+                    Outcome::Failed => {
+                        // Server still down, keep the pending records on disk
+                        let mut pending: Vec<Value> = chunks[index..].concat();
+                        pending.append(&mut self.records.lock().unwrap());
+                        self.save(pending);
+                        return delivered;
+                    },
+                }
+            }
+            if let Err(e) = fs::remove_file(&self.spool){
+                error!("Zinc spool: {}", e);
+            }
+        }
+        let records = std::mem::take(&mut *self.records.lock().unwrap());
+        for chunk in records.chunks(self.batch_size){
+            match self.send(chunk).await{
+                Outcome::Delivered => delivered += chunk.len(),
+                Outcome::Rejected => {},
+                Outcome::Failed => self.spill(chunk),
+            }
+        }
+        delivered
+    }
+
+    async fn send(&self, records: &[Value]) -> Outcome{
+        let body = Value::Array(records.to_vec());
+        for attempt in 0..=self.retries{
+            if attempt > 0{
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt - 1))).await;
+            }
+            let error = match self.zinc.publish(&body).await{
+                Ok(response) => {
+                    debug!("Zinc response: {}", response);
+                    return Outcome::Delivered;
+                },
+                Err(e) => e,
+            };
+            // A batch Zinc does not accept will never be accepted, so it is
+            // not retried nor spilled
+            let status = error.downcast_ref::<reqwest::Error>().and_then(|e| e.status());
+            if status.is_some_and(|status| status.is_client_error()){
+                error!("Zinc rejected {} records: {}: {}", records.len(), error, body);
+                return Outcome::Rejected;
+            }
+            error!("Zinc attempt {} of {}: {}", attempt + 1, self.retries + 1, error);
+        }
+        Outcome::Failed
+    }
+
+    fn spill(&self, records: &[Value]){
+        if records.is_empty(){
+            return;
+        }
+        let mut spooled = read_spool(&self.spool);
+        spooled.extend_from_slice(records);
+        self.save(spooled);
+        debug!("Zinc: {} records spilled to {:?}", records.len(), self.spool);
+    }
+
+    // Replaces the spool, dropping the oldest records over max_spool
+    fn save(&self, mut records: Vec<Value>){
+        if records.len() > self.max_spool{
+            let excess = records.len() - self.max_spool;
+            error!("Zinc spool full: {} records lost", excess);
+            records.drain(..excess);
+        }
+        if let Err(e) = write_spool(&self.spool, &records){
+            error!("Zinc: {} records lost: {}", records.len(), e);
+        }
+    }
+}
+
+enum Outcome{
+    Delivered,
+    Rejected,
+    Failed,
+}
+
+fn read_spool(spool: &Path) -> Vec<Value>{
+    match fs::read_to_string(spool){
+        Ok(data) => data.lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn write_spool(spool: &Path, records: &[Value]) -> Result<(), std::io::Error>{
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(spool)?;
+    for record in records{
+        writeln!(file, "{}", record)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{read_spool, ZincBuffer};
+    use crate::models::Zinc;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn spill_when_server_is_down() {
+        let spool = std::env::temp_dir().join(format!("watchdog-zinc-{}.spool", std::process::id()));
+        let zinc = Zinc::new("127.0.0.1:9", "test", "token");
+        let buffer = ZincBuffer::new(zinc, 10, 0, spool.to_str().unwrap(), 100);
+        buffer.push(&json!([{"test": 1}, {"test": 2}]));
+        buffer.push(&json!({"test": 3}));
+        assert_eq!(buffer.pending(), 3);
+        assert_eq!(buffer.flush().await, 0);
+        assert_eq!(buffer.pending(), 0);
+        assert_eq!(read_spool(&spool), vec![json!({"test": 1}), json!({"test": 2}), json!({"test": 3})]);
+        buffer.push(&json!({"test": 4}));
+        assert_eq!(buffer.flush().await, 0);
+        assert_eq!(read_spool(&spool).len(), 4);
+        std::fs::remove_file(&spool).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cap_the_spool() {
+        let spool = std::env::temp_dir().join(format!("watchdog-zinc-cap-{}.spool", std::process::id()));
+        let zinc = Zinc::new("127.0.0.1:9", "test", "token");
+        let buffer = ZincBuffer::new(zinc, 2, 0, spool.to_str().unwrap(), 3);
+        buffer.push(&json!([{"test": 1}, {"test": 2}, {"test": 3}]));
+        assert_eq!(buffer.flush().await, 0);
+        buffer.push(&json!([{"test": 4}, {"test": 5}]));
+        assert_eq!(buffer.flush().await, 0);
+        assert_eq!(read_spool(&spool), vec![json!({"test": 3}), json!({"test": 4}), json!({"test": 5})]);
+        std::fs::remove_file(&spool).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use serde_json::{Map, Value};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use super::ZincBuffer;
+
+// Events from the Zinc client itself and from the HTTP stack would feed
+// back into the buffer every time it is flushed.
+const IGNORED_TARGETS: [&str; 6] = ["mastodon_watchdog::models::zinc_buffer", "reqwest",
+    "hyper", "h2", "rustls", "tokio"];
+
+// Forwards tracing events, with the fields of their spans, as records to Zinc
+pub struct ZincLayer{
+    buffer: Arc<ZincBuffer>,
+}
+
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields{
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug){
+        self.0.insert(field.name().to_string(), Value::from(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str){
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64){
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64){
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64){
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool){
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+}
+
+impl ZincLayer{
+    pub fn new(buffer: Arc<ZincBuffer>) -> Self{
+        Self { buffer }
+    }
+}
+
+impl<S> Layer<S> for ZincLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>){
+        if let Some(span) = ctx.span(id){
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>){
+        if let Some(span) = ctx.span(id){
+            let mut extensions = span.extensions_mut();
+            if let Some(fields) = extensions.get_mut::<Fields>(){
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>){
+        let metadata = event.metadata();
+        if IGNORED_TARGETS.iter().any(|target| metadata.target().starts_with(target)){
+            return;
+        }
+        let mut fields = Fields::default();
+        if let Some(scope) = ctx.event_scope(event){
+            for span in scope.from_root(){
+                if let Some(span_fields) = span.extensions().get::<Fields>(){
+                    for (key, value) in span_fields.0.iter(){
+                        fields.0.insert(key.to_string(), value.clone());
+                    }
+                }
+            }
+        }
+        event.record(&mut fields);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_micros() as u64)
+            .unwrap_or_default();
+        let mut record = fields.0;
+        record.insert("_timestamp".to_string(), Value::from(timestamp));
+        record.insert("src".to_string(), Value::from("watchdog"));
+        record.insert("type".to_string(), Value::from("log"));
+        record.insert("level".to_string(), Value::from(metadata.level().as_str()));
+        record.insert("target".to_string(), Value::from(metadata.target()));
+        self.buffer.push(&Value::Object(record));
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::ZincLayer;
+    use crate::models::{Zinc, ZincBuffer};
+    use std::sync::Arc;
+    use tracing::{info, info_span};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn forward_events_with_span_fields() {
+        let zinc = Zinc::new("127.0.0.1:9", "test", "token");
+        let buffer = Arc::new(ZincBuffer::new(zinc, 10, 0, "unused.spool", 100));
+        let subscriber = tracing_subscriber::registry()
+            .with(ZincLayer::new(Arc::clone(&buffer)));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("process", notification_id = "123", category = "idea");
+            let _enter = span.enter();
+            info!(delivered = true, "Feedback sent");
+        });
+        let records = buffer.take();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record["notification_id"], "123");
+        assert_eq!(record["category"], "idea");
+        assert_eq!(record["delivered"], true);
+        assert_eq!(record["message"], "Feedback sent");
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["type"], "log");
+    }
+}
@@ -0,0 +1,93 @@
+//! HMAC-SHA256 signatures for the requests sent to the feedback service.
+//!
+//! The signature covers `{timestamp}.{body}`, where the timestamp is the Unix
+//! time in seconds sent in the `X-Watchdog-Timestamp` header. Receivers verify
+//! the `X-Watchdog-Signature` header with `verify` and reject requests whose
+//! timestamp is too old to prevent replays.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+pub const TIMESTAMP_HEADER: &str = "X-Watchdog-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Watchdog-Signature";
+const PREFIX: &str = "sha256=";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError{
+    Malformed,
+    Expired,
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self {
+            SignatureError::Malformed => write!(f, "malformed signature"),
+            SignatureError::Expired => write!(f, "timestamp outside the tolerance"),
+            SignatureError::Mismatch => write!(f, "signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError{}
+
+fn get_mac(secret: &[u8], timestamp: i64, body: &[u8]) -> HmacSha256{
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Returns the value of the signature header, `sha256=<hex digest>`
+pub fn sign(secret: &[u8], timestamp: i64, body: &[u8]) -> String{
+    let digest = get_mac(secret, timestamp, body).finalize().into_bytes();
+    format!("{}{}", PREFIX, hex::encode(digest))
+}
+
+/// Checks the signature header of a request received at `now`, both in Unix
+/// seconds, accepting timestamps up to `tolerance` seconds away
+pub fn verify(secret: &[u8], timestamp: i64, body: &[u8], signature: &str,
+        now: i64, tolerance: i64) -> Result<(), SignatureError>{
+    let digest = signature.strip_prefix(PREFIX)
+        .and_then(|digest| hex::decode(digest).ok())
+        .ok_or(SignatureError::Malformed)?;
+    if (now - timestamp).abs() > tolerance{
+        return Err(SignatureError::Expired);
+    }
+    get_mac(secret, timestamp, body)
+        .verify_slice(&digest)
+        .map_err(|_| SignatureError::Mismatch)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{sign, verify, SignatureError};
+
+    const SECRET: &[u8] = b"secret";
+    const BODY: &[u8] = br#"{"category":"idea"}"#;
+
+    #[test]
+    fn sign_and_verify() {
+        let signature = sign(SECRET, 1690000000, BODY);
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(verify(SECRET, 1690000000, BODY, &signature, 1690000100, 300), Ok(()));
+    }
+
+    #[test]
+    fn reject_invalid_signatures() {
+        let signature = sign(SECRET, 1690000000, BODY);
+        assert_eq!(verify(b"other", 1690000000, BODY, &signature, 1690000000, 300),
+            Err(SignatureError::Mismatch));
+        assert_eq!(verify(SECRET, 1690000000, b"{}", &signature, 1690000000, 300),
+            Err(SignatureError::Mismatch));
+        assert_eq!(verify(SECRET, 1690000001, BODY, &signature, 1690000000, 300),
+            Err(SignatureError::Mismatch));
+        assert_eq!(verify(SECRET, 1690000000, BODY, &signature, 1690001000, 300),
+            Err(SignatureError::Expired));
+        assert_eq!(verify(SECRET, 1690000000, BODY, "md5=abc", 1690000000, 300),
+            Err(SignatureError::Malformed));
+    }
+}